@@ -1,55 +1,105 @@
 use std::fmt::Debug;
+use std::path::PathBuf;
 use std::rc::Rc;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::Arc;
 
+use crate::compare::{Compare, CompareView, SPLIT_GRAB_DISTANCE, SPLIT_LINE_WIDTH};
+use crate::crop::Crop;
+use crate::diff::{Diff, DiffStats};
 use crate::event::MeviEvent;
+use crate::exec;
+use crate::export::{self, ExportError};
+use crate::fileops::{self, FileOp};
+use crate::files::FileList;
 use crate::font::loader::LoadedFont;
-use crate::font::{FontDrawer, RenderString, ToRenderLine};
+use crate::font::{FontDrawer, RenderLine, RenderString, ToRenderLine};
+use crate::img::adjust::Adjustment;
+use crate::img::histogram::{Histogram, BINS};
 use crate::img::MeviImage;
+use crate::keys::KeyMap;
 use crate::menu::{Menu, MenuAction};
-use crate::screen::RenderVisualInfo;
-use crate::state::MeviState;
-use crate::util::{Rect, GRAY_RENDER_COLOR, INITIAL_SIZE, TITLE};
+use crate::prompt::{Prompt, PromptEvent, PromptKind};
+use crate::resize::Resize;
+use crate::screen::{PixelFormat, RenderVisualInfo};
+use crate::state::{Drag, MeviState};
+use crate::util::{
+    Rect, CROP_SHADE_RENDER_COLOR, GRAY_RENDER_COLOR, HISTOGRAM_BG_RENDER_COLOR,
+    HISTOGRAM_BLUE_RENDER_COLOR, HISTOGRAM_GREEN_RENDER_COLOR, HISTOGRAM_HEIGHT,
+    HISTOGRAM_LUMA_RENDER_COLOR, HISTOGRAM_PADDING, HISTOGRAM_RED_RENDER_COLOR, INITIAL_SIZE,
+    MARK_INDICATOR_SIZE, MARK_RENDER_COLOR, TITLE, WHITE_RENDER_COLOR,
+};
+use crate::wallpaper;
 use crate::{Atoms, CLI};
 use anyhow::Result;
+use image::DynamicImage;
 use x11rb::connection::Connection;
 use x11rb::image::Image;
-use x11rb::protocol::render::{ConnectionExt as _, CreatePictureAux, PolyEdge, PolyMode, Repeat};
+use x11rb::protocol::render::{
+    ConnectionExt as _, CreatePictureAux, PictOp, PolyEdge, PolyMode, Repeat,
+};
 use x11rb::protocol::xproto::{
-    ConnectionExt, CreateGCAux, CreateWindowAux, EventMask, FillStyle, PropMode, Screen,
-    WindowClass,
+    Atom, ClientMessageEvent, ConnectionExt, CreateGCAux, CreateWindowAux, EventMask, FillStyle,
+    Pixmap, PropMode, Screen, Window, WindowClass,
 };
 use x11rb::wrapper::ConnectionExt as _;
 
+/// Shown when every file was removed from the list but can be brought back.
+static NO_FILES_STATUS: &str = "No files left, Ctrl+Z undoes and Esc quits";
+
+#[allow(clippy::redundant_allocation)]
 pub struct Mevi<'a, C: Connection> {
     pub atoms: Atoms,
     conn: Rc<&'a C>,
+    /// The same connection, for waking the event loop from worker threads.
+    shared_conn: Arc<C>,
     screen: &'a Screen,
     vis_info: Rc<RenderVisualInfo>,
     file_info: RenderString,
     pub state: MeviState<'a, C>,
     pub font_drawer: Rc<FontDrawer>,
     image: MeviImage,
+    /// The page shown next to `image` in a spread.
+    second: Option<MeviImage>,
+    /// The differences between `image` and `second` in the diff view.
+    diff: Option<(MeviImage, DiffStats)>,
+    /// Where the top left corner of `image` was last drawn in the window.
+    origin: (i32, i32),
+    histogram: Option<Histogram>,
+    /// The histogram being computed for the current image.
+    histogram_job: Option<Receiver<Histogram>>,
+    pixel_format: PixelFormat,
+    pub files: FileList,
+    pub keymap: KeyMap,
+    pub prompt: Option<Prompt>,
+    status: Option<String>,
+    undo_stack: Vec<FileOp>,
     pub menu: Menu<'a, C>,
     pub w: u16,
     pub h: u16,
 }
 
-impl<'a, C: Connection + Debug> Mevi<'a, C> {
+impl<'a, C: Connection + Debug + Send + Sync + 'static> Mevi<'a, C> {
     pub fn init(
-        conn: &'a C,
+        shared_conn: &'a Arc<C>,
         screen: &'a Screen,
         atoms: Atoms,
         image: MeviImage,
         bg_img: Image,
+        pixel_format: PixelFormat,
+        files: FileList,
     ) -> Result<Self> {
+        let conn: &'a C = shared_conn;
         let mut state = MeviState::init(conn)?;
         let vis_info = Rc::new(RenderVisualInfo::new(conn, screen)?);
         let font = LoadedFont::new(conn, vis_info.render.pict_format)?;
         let font_drawer = Rc::new(FontDrawer::new(font));
+        let keymap = KeyMap::new(conn)?;
 
-        let path = CLI.path.to_string_lossy().to_string();
+        let path = files.current().to_string_lossy().to_string();
         let title = format!("{TITLE} - {path}");
-        let image_info = image.to_lines(&font_drawer);
+        let mut image_info = image.to_lines(&font_drawer);
+        image_info.extend(files.to_lines(&font_drawer));
         let file_info = RenderString::new(image_info).line_gap(5).pad(5);
 
         Self::set_bg(conn, &state, screen, bg_img)?;
@@ -61,6 +111,7 @@ impl<'a, C: Connection + Debug> Mevi<'a, C> {
                 | EventMask::STRUCTURE_NOTIFY
                 | EventMask::KEY_RELEASE
                 | EventMask::BUTTON_PRESS
+                | EventMask::BUTTON_RELEASE
                 | EventMask::POINTER_MOTION,
         );
 
@@ -98,19 +149,37 @@ impl<'a, C: Connection + Debug> Mevi<'a, C> {
             Rc::clone(&font_drawer),
         )?;
 
-        Ok(Self {
+        let mut mevi = Self {
             atoms,
             conn,
+            shared_conn: Arc::clone(shared_conn),
             screen,
             vis_info,
             file_info,
             state,
             font_drawer,
             image,
+            second: None,
+            diff: None,
+            origin: (0, 0),
+            histogram: None,
+            histogram_job: None,
+            pixel_format,
+            files,
+            keymap,
+            prompt: None,
+            status: None,
+            undo_stack: vec![],
             menu,
             w: INITIAL_SIZE.0,
             h: INITIAL_SIZE.1,
-        })
+        };
+        if mevi.state.layout.spread || mevi.state.compare.is_some() {
+            mevi.reload_image()?;
+        } else {
+            mevi.start_histogram()?;
+        }
+        Ok(mevi)
     }
 
     fn set_window_properties(
@@ -120,21 +189,7 @@ impl<'a, C: Connection + Debug> Mevi<'a, C> {
         atoms: &Atoms,
         title: &str,
     ) -> Result<()> {
-        conn.change_property8(
-            PropMode::REPLACE,
-            wid,
-            atoms.WM_NAME,
-            atoms.STRING,
-            title.as_bytes(),
-        )?;
-
-        conn.change_property8(
-            PropMode::REPLACE,
-            wid,
-            atoms._NET_WM_NAME,
-            atoms.UTF8_STRING,
-            title.as_bytes(),
-        )?;
+        Self::set_title(conn, wid, atoms, title)?;
 
         conn.change_property32(
             PropMode::REPLACE,
@@ -157,6 +212,25 @@ impl<'a, C: Connection + Debug> Mevi<'a, C> {
         Ok(())
     }
 
+    fn set_title(conn: &C, wid: u32, atoms: &Atoms, title: &str) -> Result<()> {
+        conn.change_property8(
+            PropMode::REPLACE,
+            wid,
+            atoms.WM_NAME,
+            atoms.STRING,
+            title.as_bytes(),
+        )?;
+
+        conn.change_property8(
+            PropMode::REPLACE,
+            wid,
+            atoms._NET_WM_NAME,
+            atoms.UTF8_STRING,
+            title.as_bytes(),
+        )?;
+        Ok(())
+    }
+
     pub fn set_bg(conn: &C, st: &MeviState<C>, sc: &Screen, i: Image) -> Result<()> {
         conn.create_pixmap(
             sc.root_depth,
@@ -200,10 +274,19 @@ impl<'a, C: Connection + Debug> Mevi<'a, C> {
             &CreateGCAux::default().graphics_exposures(0),
         )?;
 
-        conn.create_pixmap(sc.root_depth, st.pms.image.pixmap(), sc.root, i.w, i.h)?;
+        Self::put_image(conn, st, sc, st.pms.image.pixmap(), i)
+    }
+
+    pub fn put_image(
+        conn: &C,
+        st: &MeviState<C>,
+        sc: &Screen,
+        pixmap: Pixmap,
+        i: &MeviImage,
+    ) -> Result<()> {
+        conn.create_pixmap(sc.root_depth, pixmap, sc.root, i.w, i.h)?;
 
-        i.inner
-            .put(conn, st.pms.image.pixmap(), st.gcs.buffer.gcontext(), 0, 0)?;
+        i.inner.put(conn, pixmap, st.gcs.buffer.gcontext(), 0, 0)?;
         Ok(())
     }
 
@@ -237,16 +320,115 @@ impl<'a, C: Connection + Debug> Mevi<'a, C> {
         loop {
             let event = self.conn.wait_for_event()?;
 
-            match MeviEvent::handle(self, event) {
+            let event = MeviEvent::handle(self, event);
+            let event = if self.files.len() == 0 && !works_without_files(&event) {
+                self.set_status(NO_FILES_STATUS);
+                MeviEvent::Idle
+            } else {
+                event
+            };
+            let passive = matches!(
+                event,
+                MeviEvent::DrawImage
+                    | MeviEvent::HistogramReady
+                    | MeviEvent::Idle
+                    | MeviEvent::Error(_)
+            );
+            if !passive && self.status.take().is_some() {
+                self.state.should_redraw = true;
+            }
+
+            match event {
                 MeviEvent::DrawImage => self.state.should_redraw = true,
                 MeviEvent::ToggleFileInfo => self.toggle_show_file_info(),
+                MeviEvent::ToggleHistogram => self.toggle_histogram()?,
                 MeviEvent::ToggleFullscreen => self.toggle_fullscreen()?,
+                MeviEvent::ToggleSpread => self.toggle_spread()?,
+                MeviEvent::ToggleReadingDirection => self.toggle_reading_direction()?,
+                MeviEvent::ToggleCompare => self.toggle_compare()?,
+                MeviEvent::NextCompareView => self.update_compare(|c| c.view = c.view.next())?,
+                MeviEvent::FlipCompare => self.update_compare(Compare::flip)?,
+                MeviEvent::NextDiffStyle => {
+                    self.update_compare(|c| c.diff_style = c.diff_style.next())?
+                }
+                MeviEvent::DragStart(x, y) => self.start_drag(x, y),
+                MeviEvent::Drag(x, y) => self.drag_to(x, y),
+                MeviEvent::DragEnd => self.state.drag = None,
+                MeviEvent::ToggleCrop => self.toggle_crop(),
+                MeviEvent::NextCropAspect => self.update_crop(Crop::next_aspect),
+                MeviEvent::NudgeCrop(dx, dy, resize) => self.nudge_crop(dx, dy, resize),
+                MeviEvent::SaveCrop => self.open_crop_prompt(),
+                MeviEvent::SaveAs => self.open_save_prompt(),
+                MeviEvent::Resize => self.open_resize_prompt(),
+                MeviEvent::NextPage => self.change_page(true)?,
+                MeviEvent::PrevPage => self.change_page(false)?,
+                MeviEvent::ZoomIn => self.zoom(MeviImage::zoom_in)?,
+                MeviEvent::ZoomOut => self.zoom(MeviImage::zoom_out)?,
+                MeviEvent::ZoomFit => self.zoom(|i, c| i.set_zoom(c, None))?,
+                MeviEvent::ZoomActual => self.zoom(|i, c| i.set_zoom(c, Some(1.0)))?,
+                MeviEvent::NextToneMap => self.adjust_tone(MeviImage::next_tone_map)?,
+                MeviEvent::ExposureUp => self.adjust_tone(MeviImage::exposure_up)?,
+                MeviEvent::ExposureDown => self.adjust_tone(MeviImage::exposure_down)?,
+                MeviEvent::ToggleColorManagement => self.toggle_color_management()?,
+                MeviEvent::NextChannel => self.next_channel()?,
+                MeviEvent::Adjust(change) => self.adjust(change)?,
+                MeviEvent::NextFile => self.next_file()?,
+                MeviEvent::PrevFile => self.prev_file()?,
+                MeviEvent::ToggleMark => self.update_marks(FileList::toggle_mark)?,
+                MeviEvent::MarkAll => self.update_marks(FileList::mark_all)?,
+                MeviEvent::UnmarkAll => self.update_marks(FileList::unmark_all)?,
+                MeviEvent::InvertMarks => self.update_marks(FileList::invert_marks)?,
+                MeviEvent::Trash => self.trash_current()?,
+                MeviEvent::Rename => self.open_rename_prompt(),
+                MeviEvent::CopyToDestination => self.copy_to_destination(),
+                MeviEvent::MoveToDestination => self.move_to_destination()?,
+                MeviEvent::Undo => self.undo()?,
+                MeviEvent::Prompt(e) => self.handle_prompt_event(e)?,
+                MeviEvent::KeyHandlerPrefix => {
+                    info!("Waiting for key handler key");
+                    self.state.awaiting_handler_key = true;
+                }
+                MeviEvent::KeyHandler(key) => {
+                    self.state.awaiting_handler_key = false;
+                    self.run_key_handler(&key)?;
+                }
+                MeviEvent::KeyHandlerCancel => {
+                    info!("Cancelled key handler");
+                    self.state.awaiting_handler_key = false;
+                }
                 MeviEvent::Menu(menu_evt) => match self.menu.handle_event(menu_evt)? {
+                    action
+                        if self.files.len() == 0
+                            && !matches!(
+                                action,
+                                MenuAction::ToggleFileInfo
+                                    | MenuAction::Fullscreen
+                                    | MenuAction::Undo
+                                    | MenuAction::Exit
+                                    | MenuAction::None
+                            ) =>
+                    {
+                        self.set_status(NO_FILES_STATUS)
+                    }
                     MenuAction::ToggleFileInfo => self.toggle_show_file_info(),
+                    MenuAction::ToggleHistogram => self.toggle_histogram()?,
                     MenuAction::Fullscreen => self.toggle_fullscreen()?,
+                    MenuAction::Compare => self.toggle_compare()?,
+                    MenuAction::Crop => self.toggle_crop(),
+                    MenuAction::SaveAs => self.open_save_prompt(),
+                    MenuAction::Resize => self.open_resize_prompt(),
+                    MenuAction::SetWallpaper => self.set_wallpaper(),
+                    MenuAction::ToggleMark => self.update_marks(FileList::toggle_mark)?,
+                    MenuAction::Trash => self.trash_current()?,
+                    MenuAction::Rename => self.open_rename_prompt(),
+                    MenuAction::CopyToDestination => self.copy_to_destination(),
+                    MenuAction::MoveToDestination => self.move_to_destination()?,
+                    MenuAction::Undo => self.undo()?,
+                    MenuAction::ResetAdjustments => self.adjust(Adjustment::Reset)?,
                     MenuAction::Exit => self.state.should_exit = true,
                     MenuAction::None => {}
                 },
+                MeviEvent::HistogramReady => self.receive_histogram(),
                 MeviEvent::Exit => self.state.should_exit = true,
                 MeviEvent::Error(e) => err!("{e:?}"),
                 MeviEvent::Idle => {}
@@ -294,6 +476,923 @@ impl<'a, C: Connection + Debug> Mevi<'a, C> {
         Ok(())
     }
 
+    fn run_key_handler(&mut self, key: &str) -> Result<()> {
+        let paths = self.files.selection();
+        if paths.is_empty() {
+            self.set_status(
+                "Files inside archives or not saved yet can't be passed to the key handler",
+            );
+            return Ok(());
+        }
+        match exec::run_key_handler(key, &paths) {
+            Ok(changed) if changed.iter().any(|p| p == self.files.current()) => {
+                self.reload_image()?
+            }
+            Ok(_) => {}
+            Err(e) => err!("{e}"),
+        }
+        Ok(())
+    }
+
+    /// The area each image is fitted into.
+    fn page_area(&self) -> (u32, u32) {
+        let (w, h) = (
+            self.screen.width_in_pixels as u32,
+            self.screen.height_in_pixels as u32,
+        );
+        match self.state.compare {
+            Some(compare) if compare.view == CompareView::SideBySide => {
+                (w.saturating_sub(self.state.layout.gap as u32) / 2, h)
+            }
+            Some(_) => (w, h),
+            None => self.state.layout.page_area((w, h)),
+        }
+    }
+
+    fn open_current(&self) -> Result<MeviImage> {
+        MeviImage::open(
+            *self.conn,
+            self.files.current_entry(),
+            self.page_area(),
+            self.pixel_format.clone(),
+            self.image.look,
+        )
+    }
+
+    /// Loads the file shown next to the current one in a spread or compared
+    /// with it, if any. A failure only costs the second image.
+    fn open_second(&self) -> Option<MeviImage> {
+        let (index, len) = (self.files.index(), self.files.len());
+        let i = if self.state.compare.is_some() {
+            (len > 1).then_some((index + 1) % len)?
+        } else {
+            self.state.layout.partner(index, len)?
+        };
+        let entry = self.files.entry(i)?;
+        match MeviImage::open(
+            *self.conn,
+            entry,
+            self.page_area(),
+            self.pixel_format.clone(),
+            self.image.look,
+        ) {
+            Ok(image) => Some(image),
+            Err(e) => {
+                err!("Failed to load {:?}: {e}", entry.path);
+                None
+            }
+        }
+    }
+
+    fn reload_image(&mut self) -> Result<()> {
+        let path = self.files.current().to_path_buf();
+        match self.open_current() {
+            Ok(image) => {
+                self.show_image(image)?;
+                info!("Reloaded image {path:?}");
+            }
+            Err(e) => err!("Failed to reload {path:?}: {e}"),
+        }
+        Ok(())
+    }
+
+    fn next_file(&mut self) -> Result<()> {
+        if self.files.len() > 1 {
+            let i = if self.state.layout.spread {
+                self.state
+                    .layout
+                    .next_start(self.files.index(), self.files.len())
+            } else {
+                self.files.next_index()
+            };
+            self.load_file(i, true)?;
+        }
+        Ok(())
+    }
+
+    fn prev_file(&mut self) -> Result<()> {
+        if self.files.len() > 1 {
+            let i = if self.state.layout.spread {
+                self.state
+                    .layout
+                    .prev_start(self.files.index(), self.files.len())
+            } else {
+                self.files.prev_index()
+            };
+            self.load_file(i, false)?;
+        }
+        Ok(())
+    }
+
+    fn toggle_spread(&mut self) -> Result<()> {
+        self.state.layout.spread = !self.state.layout.spread;
+        if self.state.layout.spread {
+            self.state.compare = None;
+        }
+        let state = if self.state.layout.spread {
+            "on"
+        } else {
+            "off"
+        };
+        self.set_status(format!("two page spread: {state}"));
+        let start = self.state.layout.spread_start(self.files.index());
+        self.files.set_index(start);
+        self.reload_image()
+    }
+
+    fn toggle_reading_direction(&mut self) -> Result<()> {
+        self.state.layout.rtl = !self.state.layout.rtl;
+        let direction = if self.state.layout.rtl {
+            "right to left"
+        } else {
+            "left to right"
+        };
+        self.set_status(format!("reading {direction}"));
+        self.state.should_redraw = true;
+        Ok(())
+    }
+
+    /// Renders compared images at the same scale, the smaller of the two, so
+    /// their pixels line up.
+    fn sync_zoom(&mut self) -> Result<()> {
+        let Some(second) = self
+            .second
+            .as_mut()
+            .filter(|_| self.state.compare.is_some())
+        else {
+            return Ok(());
+        };
+        let scale = self.image.scale.min(second.scale);
+        let diff = self.diff.as_mut().map(|(diff, _)| diff);
+        for image in [Some(&mut self.image), Some(second), diff]
+            .into_iter()
+            .flatten()
+        {
+            if image.scale != scale {
+                image.set_zoom(*self.conn, Some(scale))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Computes the differences between the compared images when the diff
+    /// view is on. Images that can't be compared are reported in the status
+    /// bar.
+    fn make_diff(&mut self) -> Result<Option<(MeviImage, DiffStats)>> {
+        let (Some(compare), Some(second)) = (self.state.compare, &self.second) else {
+            return Ok(None);
+        };
+        if compare.view != CompareView::Diff {
+            return Ok(None);
+        }
+
+        let a = self.image.pixels()?;
+        let diff = match Diff::compute(&a, &*second.pixels()?, CLI.diff_threshold) {
+            Ok(diff) => diff,
+            Err(e) => {
+                drop(a);
+                self.report_error("Can't diff", e);
+                return Ok(None);
+            }
+        };
+        let rendered = DynamicImage::ImageRgb8(diff.render(compare.diff_style, &a));
+        let image = MeviImage::from_image(
+            *self.conn,
+            "diff",
+            rendered,
+            self.page_area(),
+            self.pixel_format.clone(),
+        )?;
+        Ok(Some((image, diff.stats)))
+    }
+
+    /// Recomputes the diff after the comparison changed, leaving the compared
+    /// images alone.
+    fn refresh_diff(&mut self) -> Result<()> {
+        if self.diff.take().is_some() {
+            self.conn.free_pixmap(self.state.pms.diff_image.pixmap())?;
+        }
+        self.diff = self.make_diff()?;
+        self.sync_zoom()?;
+        if let Some((diff, _)) = &self.diff {
+            let pixmap = self.state.pms.diff_image.pixmap();
+            Self::put_image(*self.conn, &self.state, self.screen, pixmap, diff)?;
+        }
+        Ok(())
+    }
+
+    fn toggle_compare(&mut self) -> Result<()> {
+        if self.state.compare.take().is_some() {
+            self.set_status("compare: off");
+        } else if self.files.len() < 2 {
+            self.set_status("Nothing to compare with");
+            return Ok(());
+        } else {
+            let compare = Compare::new(CLI.compare_view);
+            self.set_status(format!("compare: {}", compare.view));
+            self.state.compare = Some(compare);
+            self.state.layout.spread = false;
+        }
+        self.reload_image()
+    }
+
+    /// Changes the comparison with `f`, re-rendering the images only if they
+    /// have to fit a different area now.
+    fn update_compare(&mut self, f: fn(&mut Compare)) -> Result<()> {
+        let area = self.page_area();
+        let Some(compare) = &mut self.state.compare else {
+            self.set_status("Not comparing, press k to compare with the next file");
+            return Ok(());
+        };
+        f(compare);
+        let status = format!("compare: {}", compare.describe());
+        self.set_status(status);
+        if self.page_area() != area {
+            self.reload_image()
+        } else {
+            self.refresh_diff()?;
+            self.update_file_info()?;
+            self.state.should_redraw = true;
+            Ok(())
+        }
+    }
+
+    /// Starts selecting the area to crop when cropping. Otherwise starts
+    /// panning, or moving the wipe line when the drag starts on it.
+    fn start_drag(&mut self, x: i16, y: i16) {
+        if self.state.crop.is_some() {
+            let point = self.view_to_image(x, y);
+            self.update_crop(|crop| crop.start(point));
+            self.state.drag = Some(Drag::Crop);
+            return;
+        }
+        let on_split = match self.state.compare {
+            Some(compare) if compare.view == CompareView::Wipe && self.second.is_some() => {
+                (x - compare.split_x(self.w)).abs() <= SPLIT_GRAB_DISTANCE
+            }
+            _ => false,
+        };
+        self.state.drag = Some(if on_split {
+            Drag::Split
+        } else {
+            Drag::Pan(x, y)
+        });
+    }
+
+    fn drag_to(&mut self, x: i16, y: i16) {
+        match self.state.drag {
+            Some(Drag::Split) => {
+                if let Some(compare) = &mut self.state.compare {
+                    compare.set_split_x(x, self.w);
+                }
+            }
+            Some(Drag::Pan(last_x, last_y)) => {
+                let (pan_x, pan_y) = self.state.pan;
+                self.state.pan = (pan_x + (x - last_x) as i32, pan_y + (y - last_y) as i32);
+                self.state.drag = Some(Drag::Pan(x, y));
+            }
+            Some(Drag::Crop) => {
+                let point = self.view_to_image(x, y);
+                if let Some(crop) = &mut self.state.crop {
+                    crop.drag_to(point);
+                }
+            }
+            None => return,
+        }
+        self.state.should_redraw = true;
+    }
+
+    /// The pixel of the image under the window position `(x, y)`, clamped to
+    /// the image's edges.
+    fn view_to_image(&self, x: i16, y: i16) -> (u32, u32) {
+        let (ox, oy) = self.origin;
+        let to_image = |v: i16, o: i32, max: u32| {
+            ((v as i32 - o) as f32 / self.image.scale)
+                .round()
+                .clamp(0.0, max as f32) as u32
+        };
+        (
+            to_image(x, ox, self.image.ow),
+            to_image(y, oy, self.image.oh),
+        )
+    }
+
+    fn toggle_crop(&mut self) {
+        self.state.crop = match self.state.crop {
+            Some(_) => None,
+            None => Some(Crop::new((self.image.ow, self.image.oh))),
+        };
+        self.state.drag = None;
+        self.state.should_redraw = true;
+    }
+
+    fn update_crop(&mut self, f: impl FnOnce(&mut Crop)) {
+        if let Some(crop) = &mut self.state.crop {
+            f(crop);
+            self.state.should_redraw = true;
+        }
+    }
+
+    /// Nudges the selection by about one screen pixel at the current zoom.
+    fn nudge_crop(&mut self, dx: i32, dy: i32, resize: bool) {
+        let step = (1.0 / self.image.scale).ceil().max(1.0) as i32;
+        self.update_crop(|crop| crop.nudge((dx * step, dy * step), resize));
+    }
+
+    /// Where copies of the current file are saved: next to the file, or next
+    /// to the archive it is in.
+    fn save_base(&self) -> PathBuf {
+        let entry = self.files.current_entry();
+        match &entry.member {
+            Some(member) => member.archive.path.clone(),
+            None => entry.path.clone(),
+        }
+    }
+
+    /// The current file's name with `suffix` added to the stem, and `ext`
+    /// in place of the extension if given.
+    fn derived_name(&self, suffix: &str, ext: Option<&str>) -> String {
+        let path = self.files.current();
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let ext = match (ext, path.extension()) {
+            (Some(ext), _) => ext.to_string(),
+            (None, Some(ext)) => ext.to_string_lossy().to_string(),
+            (None, None) => "png".into(),
+        };
+        format!("{stem}{suffix}.{ext}")
+    }
+
+    fn open_crop_prompt(&mut self) {
+        if self.state.crop.and_then(|c| c.selection).is_none() {
+            self.set_status("Drag to select the area to crop first");
+            return;
+        }
+        let name = self.derived_name("-crop", None);
+        self.prompt = Some(Prompt::new(PromptKind::SaveCrop, "Save crop as", name));
+        self.state.should_redraw = true;
+    }
+
+    /// Saves the selection as `name` next to the current file. Giving the
+    /// file's own name overwrites it in its format.
+    fn save_crop(&mut self, name: &str) -> Result<()> {
+        let Some(s) = self.state.crop.and_then(|c| c.selection) else {
+            return Ok(());
+        };
+        let target = match fileops::sibling(&self.save_base(), name) {
+            Ok(target) => target,
+            Err(e) => {
+                self.report_error("Failed to save the crop", e);
+                return Ok(());
+            }
+        };
+        let overwrite = target == self.files.current();
+        if overwrite && self.not_on_disk() {
+            return Ok(());
+        }
+        if !overwrite && target.exists() {
+            self.report_error("Failed to save the crop", ExportError::Exists(target));
+            return Ok(());
+        }
+
+        let res = self.image.pixels().and_then(|pixels| {
+            let crop = self.image.to_srgb(pixels.crop_imm(s.x, s.y, s.w, s.h));
+            export::save(&crop, &target, CLI.export)
+        });
+        if let Err(e) = res {
+            self.report_error(format!("Failed to save {}", target.display()), e);
+            return Ok(());
+        }
+        if overwrite {
+            self.state.crop = None;
+            self.reload_image()?;
+        }
+        self.set_status(format!(
+            "Saved {}x{} crop to {}",
+            s.w,
+            s.h,
+            target.display()
+        ));
+        Ok(())
+    }
+
+    fn open_save_prompt(&mut self) {
+        let mut name = self.derived_name("", Some("png"));
+        // only a file that exists has to be kept from being overwritten
+        if self.files.current_entry().on_disk() && self.files.current().ends_with(&name) {
+            name = self.derived_name("-export", Some("png"));
+        }
+        self.prompt = Some(Prompt::new(PromptKind::SaveAs, "Save as", name));
+        self.state.should_redraw = true;
+    }
+
+    /// The image as it is shown, cut to the crop selection if there is one.
+    fn export_pixels(&self) -> Result<DynamicImage> {
+        let image = self.image.shown_pixels()?;
+        Ok(match self.state.crop.and_then(|c| c.selection) {
+            Some(s) => image.crop_imm(s.x, s.y, s.w, s.h),
+            None => image,
+        })
+    }
+
+    fn set_wallpaper(&mut self) {
+        let mode = CLI.wallpaper_mode;
+        match self
+            .export_pixels()
+            .and_then(|image| wallpaper::set(&image, mode))
+        {
+            Ok(()) => self.set_status(format!("Set as wallpaper ({mode})")),
+            Err(e) => self.report_error("Failed to set the wallpaper", e),
+        }
+    }
+
+    /// The size of what is saved: the crop selection if there is one,
+    /// otherwise the whole image.
+    fn export_size(&self) -> (u32, u32) {
+        match self.state.crop.and_then(|c| c.selection) {
+            Some(s) => (s.w, s.h),
+            None => (self.image.ow, self.image.oh),
+        }
+    }
+
+    fn open_resize_prompt(&mut self) {
+        let (w, h) = self.export_size();
+        let label = format!("Resize {w}x{h} to");
+        self.prompt = Some(Prompt::new(PromptKind::Resize, label, "50%"));
+        self.state.should_redraw = true;
+    }
+
+    /// Asks where to save the image resized as `spec` says.
+    fn open_save_resized_prompt(&mut self, spec: &str) {
+        match Resize::parse(spec, self.export_size()) {
+            Ok(resize) => {
+                let name = self.derived_name(&format!("-{}x{}", resize.w, resize.h), None);
+                let label = format!("Save {}x{} ({}) as", resize.w, resize.h, resize.filter);
+                let kind = PromptKind::SaveResized(resize);
+                self.prompt = Some(Prompt::new(kind, label, name));
+            }
+            Err(e) => self.report_error("Failed to resize", e),
+        }
+        self.state.should_redraw = true;
+    }
+
+    /// Saves the image as it is shown, cut to the crop selection if there is
+    /// one and resized if asked, as `name` next to the current file. The
+    /// format follows the extension of `name`.
+    fn save_as(&mut self, name: &str, resize: Option<Resize>) {
+        let target = match fileops::sibling(&self.save_base(), name) {
+            Ok(target) if target.exists() => Err(ExportError::Exists(target).into()),
+            Ok(target) => Ok(target),
+            Err(e) => Err(anyhow::Error::from(e)),
+        };
+        let res = target.and_then(|target| {
+            let mut image = self.export_pixels()?;
+            if let Some(resize) = resize {
+                image = image.resize_exact(resize.w, resize.h, resize.filter.into());
+            }
+            export::save(&image, &target, CLI.export)?;
+            Ok(target)
+        });
+        match res {
+            Ok(target) => self.set_status(format!("Saved {}", target.display())),
+            Err(e) => self.report_error(format!("Failed to save {name}"), e),
+        }
+    }
+
+    /// Whether the current file is inside an archive or a capture that isn't
+    /// saved yet, in which case it can't be changed on disk.
+    fn not_on_disk(&mut self) -> bool {
+        let entry = self.files.current_entry();
+        let status = if entry.member.is_some() {
+            "Files inside archives can't be changed"
+        } else if entry.capture.is_some() {
+            "The capture is not saved yet"
+        } else {
+            return false;
+        };
+        self.set_status(status);
+        true
+    }
+
+    /// Loads the file at index `i`, dropping files that fail to load from
+    /// the list and moving on in the given direction until one succeeds.
+    /// Exits once no loadable file is left.
+    fn load_file(&mut self, mut i: usize, forward: bool) -> Result<()> {
+        loop {
+            i = self.state.layout.spread_start(i);
+            self.files.set_index(i);
+            let path = self.files.current().to_path_buf();
+            match self.open_current() {
+                Ok(image) => return self.show_image(image),
+                Err(e) => {
+                    err!("Failed to load {path:?}: {e}");
+                    self.files.remove(i);
+                    if self.files.len() == 0 {
+                        info!("No loadable files left");
+                        self.state.should_exit = true;
+                        return Ok(());
+                    }
+                    i = if forward {
+                        i % self.files.len()
+                    } else {
+                        i.checked_sub(1).unwrap_or(self.files.len() - 1)
+                    };
+                }
+            }
+        }
+    }
+
+    fn show_image(&mut self, image: MeviImage) -> Result<()> {
+        self.free_images()?;
+        self.image = image;
+        self.second = self.open_second();
+        self.diff = self.make_diff()?;
+        self.state.pan = (0, 0);
+        if self.state.crop.is_some() {
+            self.state.crop = Some(Crop::new((self.image.ow, self.image.oh)));
+        }
+        self.sync_zoom()?;
+        self.put_images()?;
+        self.update_file_info()?;
+        self.start_histogram()?;
+        self.state.should_redraw = true;
+        self.update_title()
+    }
+
+    fn put_images(&self) -> Result<()> {
+        let (conn, st, sc) = (*self.conn, &self.state, self.screen);
+        Self::put_image(conn, st, sc, st.pms.image.pixmap(), &self.image)?;
+        if let Some(second) = &self.second {
+            Self::put_image(conn, st, sc, st.pms.spread_image.pixmap(), second)?;
+        }
+        if let Some((diff, _)) = &self.diff {
+            Self::put_image(conn, st, sc, st.pms.diff_image.pixmap(), diff)?;
+        }
+        Ok(())
+    }
+
+    fn free_images(&self) -> Result<()> {
+        self.conn.free_pixmap(self.state.pms.image.pixmap())?;
+        if self.second.is_some() {
+            self.conn
+                .free_pixmap(self.state.pms.spread_image.pixmap())?;
+        }
+        if self.diff.is_some() {
+            self.conn.free_pixmap(self.state.pms.diff_image.pixmap())?;
+        }
+        Ok(())
+    }
+
+    /// Uploads the shown images to their pixmaps again after they were
+    /// re-rendered.
+    fn refresh_image(&mut self) -> Result<()> {
+        self.free_images()?;
+        self.put_images()?;
+        self.update_file_info()?;
+        self.state.should_redraw = true;
+        Ok(())
+    }
+
+    fn change_page(&mut self, forward: bool) -> Result<()> {
+        let Some(pages) = &self.image.pages else {
+            self.set_status("Not a multi-page file");
+            return Ok(());
+        };
+        let index = if forward {
+            pages.next_index()
+        } else {
+            pages.prev_index()
+        };
+        if let Err(e) = self.image.set_page(*self.conn, index) {
+            self.report_error(format!("Failed to load page {}", index + 1), e);
+            return Ok(());
+        }
+        self.start_histogram()?;
+        self.refresh_image()
+    }
+
+    fn zoom(&mut self, f: fn(&mut MeviImage, &C) -> Result<()>) -> Result<()> {
+        f(&mut self.image, *self.conn)?;
+        if let Some(second) = &mut self.second {
+            f(second, *self.conn)?;
+        }
+        if self.image.zoom.is_none() {
+            self.state.pan = (0, 0);
+        }
+        self.sync_zoom()?;
+        info!("Zoomed to {:.2}", self.image.scale);
+        self.refresh_image()
+    }
+
+    fn adjust_tone(&mut self, f: fn(&mut MeviImage, &C) -> Result<()>) -> Result<()> {
+        f(&mut self.image, *self.conn)?;
+        if let Some(second) = &mut self.second {
+            f(second, *self.conn)?;
+        }
+        let tone = self.image.tone;
+        self.set_status(format!(
+            "tone map: {}, exposure: {:+.1} EV",
+            tone.op, tone.exposure
+        ));
+        self.refresh_image()
+    }
+
+    /// Cycles through showing all channels, each colour channel and the
+    /// alpha mask.
+    fn next_channel(&mut self) -> Result<()> {
+        let channel = self.image.look.channel.next();
+        self.image.set_channel(*self.conn, channel)?;
+        if let Some(second) = &mut self.second {
+            second.set_channel(*self.conn, channel)?;
+        }
+        self.set_status(format!("channel: {channel}"));
+        self.refresh_image()
+    }
+
+    /// Changes the display adjustments of the shown images.
+    fn adjust(&mut self, change: Adjustment) -> Result<()> {
+        let mut adjustments = self.image.look.adjustments;
+        adjustments.change(change);
+        self.image.set_adjustments(*self.conn, adjustments)?;
+        if let Some(second) = &mut self.second {
+            second.set_adjustments(*self.conn, adjustments)?;
+        }
+        self.set_status(format!("adjust: {}", adjustments.describe()));
+        self.refresh_image()
+    }
+
+    fn toggle_color_management(&mut self) -> Result<()> {
+        if !self.image.toggle_color_management(*self.conn)? {
+            self.set_status("No colour profile to manage");
+            return Ok(());
+        }
+        if let Some(second) = &mut self.second {
+            second.toggle_color_management(*self.conn)?;
+        }
+        if let Some(color) = &self.image.color {
+            let state = if color.enabled { "on" } else { "off" };
+            self.set_status(format!("colour management: {state}"));
+        }
+        self.refresh_image()
+    }
+
+    fn update_title(&self) -> Result<()> {
+        let title = if self.files.len() == 0 {
+            format!("{TITLE} - no files")
+        } else {
+            format!("{TITLE} - {}", self.files.current().to_string_lossy())
+        };
+        Self::set_title(*self.conn, self.state.window.window(), &self.atoms, &title)
+    }
+
+    /// Points the current entry at `path` after the file was moved on disk.
+    fn relocate_current(&mut self, path: PathBuf) -> Result<()> {
+        self.image.path = path.to_string_lossy().to_string();
+        self.files.set_current_path(path);
+        self.update_file_info()?;
+        self.update_title()?;
+        self.state.should_redraw = true;
+        Ok(())
+    }
+
+    /// Drops the current entry from the file list and shows the next one.
+    /// With nothing left the window stays open as long as the removal can
+    /// be undone.
+    fn remove_current(&mut self) -> Result<()> {
+        let i = self.files.index();
+        self.files.remove(i);
+        if self.files.len() == 0 {
+            info!("No files left");
+            if self.undo_stack.is_empty() {
+                self.state.should_exit = true;
+            } else {
+                self.update_file_info()?;
+                self.update_title()?;
+                self.set_status(NO_FILES_STATUS);
+            }
+            return Ok(());
+        }
+        self.load_file(i % self.files.len(), true)
+    }
+
+    fn set_status(&mut self, status: impl ToString) {
+        let status = status.to_string();
+        info!("Status: {status}");
+        self.status = Some(status);
+        self.state.should_redraw = true;
+    }
+
+    fn report_error(&mut self, msg: impl ToString, e: impl std::fmt::Display) {
+        let msg = format!("{}: {e}", msg.to_string());
+        err!("{msg}");
+        self.set_status(msg);
+    }
+
+    fn trash_current(&mut self) -> Result<()> {
+        if self.not_on_disk() {
+            return Ok(());
+        }
+        let path = self.files.current().to_path_buf();
+        match fileops::trash(&path) {
+            Ok(trashed) => {
+                info!(
+                    "Moved {:?} to {:?}, info at {:?}",
+                    trashed.original, trashed.trashed, trashed.info
+                );
+                let index = self.files.index();
+                let marked = self.files.is_marked();
+                self.undo_stack.push(FileOp::Trash {
+                    file: trashed,
+                    index,
+                    marked,
+                });
+                self.remove_current()?;
+                if self.files.len() > 0 {
+                    self.set_status(format!("Moved {} to trash", path.display()));
+                }
+            }
+            Err(e) => self.report_error(format!("Failed to trash {}", path.display()), e),
+        }
+        Ok(())
+    }
+
+    fn undo(&mut self) -> Result<()> {
+        let Some(op) = self.undo_stack.pop() else {
+            self.set_status("Nothing to undo");
+            return Ok(());
+        };
+
+        let path = match op.undo() {
+            Ok(path) => path,
+            Err(e) => {
+                self.report_error("Failed to undo", e);
+                // kept so it can be retried once the problem is fixed
+                self.undo_stack.push(op);
+                return Ok(());
+            }
+        };
+
+        match &op {
+            FileOp::Trash { index, marked, .. } => {
+                self.files.insert(*index, path, *marked);
+                self.load_file(self.files.index(), true)?;
+            }
+            FileOp::Move { to, .. } | FileOp::Rename { to, .. } => {
+                if let Some(i) = self.files.position(to) {
+                    if i == self.files.index() {
+                        self.relocate_current(path)?;
+                    } else {
+                        self.files.set_path(i, path);
+                    }
+                }
+            }
+        }
+        self.set_status(op.describe_undo());
+        Ok(())
+    }
+
+    fn open_rename_prompt(&mut self) {
+        if self.not_on_disk() {
+            return;
+        }
+        let name = self
+            .files
+            .current()
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        self.prompt = Some(Prompt::new(PromptKind::Rename, "Rename", name));
+        self.state.should_redraw = true;
+    }
+
+    fn rename_current(&mut self, name: &str) -> Result<()> {
+        let path = self.files.current().to_path_buf();
+        match fileops::rename(&path, name) {
+            Ok(target) => {
+                self.relocate_current(target.clone())?;
+                self.set_status(format!("Renamed to {name}"));
+                self.undo_stack.push(FileOp::Rename {
+                    from: path,
+                    to: target,
+                });
+            }
+            Err(e) => self.report_error(format!("Failed to rename {}", path.display()), e),
+        }
+        Ok(())
+    }
+
+    fn copy_to_destination(&mut self) {
+        if self.not_on_disk() {
+            return;
+        }
+        let path = self.files.current().to_path_buf();
+        let res = CLI
+            .destination
+            .as_deref()
+            .ok_or(fileops::FileOpError::NoDestination)
+            .and_then(|dest| fileops::copy_to(&path, dest));
+        match res {
+            Ok(target) => self.set_status(format!("Copied to {}", target.display())),
+            Err(e) => self.report_error(format!("Failed to copy {}", path.display()), e),
+        }
+    }
+
+    fn move_to_destination(&mut self) -> Result<()> {
+        if self.not_on_disk() {
+            return Ok(());
+        }
+        let path = self.files.current().to_path_buf();
+        let res = CLI
+            .destination
+            .as_deref()
+            .ok_or(fileops::FileOpError::NoDestination)
+            .and_then(|dest| fileops::move_to(&path, dest));
+        match res {
+            Ok(target) => {
+                self.relocate_current(target.clone())?;
+                self.set_status(format!("Moved to {}", target.display()));
+                self.undo_stack.push(FileOp::Move {
+                    from: path,
+                    to: target,
+                });
+            }
+            Err(e) => self.report_error(format!("Failed to move {}", path.display()), e),
+        }
+        Ok(())
+    }
+
+    fn handle_prompt_event(&mut self, e: PromptEvent) -> Result<()> {
+        let Some(prompt) = self.prompt.as_mut() else {
+            return Ok(());
+        };
+        match e {
+            PromptEvent::Input(c) => prompt.push(c),
+            PromptEvent::Backspace => prompt.pop(),
+            PromptEvent::Cancel => self.prompt = None,
+            PromptEvent::Submit => {
+                if let Some(prompt) = self.prompt.take() {
+                    match prompt.kind {
+                        PromptKind::Rename => self.rename_current(&prompt.text)?,
+                        PromptKind::SaveCrop => self.save_crop(&prompt.text)?,
+                        PromptKind::SaveAs => self.save_as(&prompt.text, None),
+                        PromptKind::Resize => self.open_save_resized_prompt(&prompt.text),
+                        PromptKind::SaveResized(resize) => self.save_as(&prompt.text, Some(resize)),
+                    }
+                }
+            }
+        }
+        self.state.should_redraw = true;
+        Ok(())
+    }
+
+    fn update_marks(&mut self, f: fn(&mut FileList)) -> Result<()> {
+        f(&mut self.files);
+        self.update_file_info()?;
+        self.state.should_redraw = true;
+        Ok(())
+    }
+
+    fn update_file_info(&mut self) -> Result<()> {
+        self.conn
+            .render_free_picture(self.state.pics.font_buffer.picture())?;
+        self.conn.free_pixmap(self.state.pms.font_buffer.pixmap())?;
+
+        let mut image_info = self.image.to_lines(&self.font_drawer);
+        if let Some(second) = &self.second {
+            let path = &second.path;
+            match self.state.compare {
+                Some(compare) => {
+                    image_info.push(RenderLine::new(
+                        &self.font_drawer,
+                        format!("compare: {}", compare.describe()),
+                    ));
+                    image_info.push(RenderLine::new(&self.font_drawer, format!("B: {path}")));
+                    if let Some((_, stats)) = &self.diff {
+                        for line in stats.summary() {
+                            image_info.push(RenderLine::new(&self.font_drawer, line));
+                        }
+                    }
+                }
+                None => image_info.push(RenderLine::new(
+                    &self.font_drawer,
+                    format!("next to: {path}"),
+                )),
+            }
+        }
+        image_info.extend(self.files.to_lines(&self.font_drawer));
+        self.file_info = RenderString::new(image_info).line_gap(5).pad(5);
+        Self::init_file_info_font_buffer(
+            *self.conn,
+            &self.state,
+            self.screen,
+            &self.vis_info,
+            &self.file_info,
+        )?;
+        Ok(())
+    }
+
     fn toggle_show_file_info(&mut self) {
         self.state.draw_info = !self.state.draw_info;
         info!(
@@ -307,42 +1406,146 @@ impl<'a, C: Connection + Debug> Mevi<'a, C> {
         self.state.should_redraw = true;
     }
 
-    pub fn calculate_rects(&mut self) -> Result<(Rect, Rect)> {
+    fn toggle_histogram(&mut self) -> Result<()> {
+        self.state.draw_histogram = !self.state.draw_histogram;
+        if self.state.draw_histogram && self.histogram.is_none() {
+            self.start_histogram()?;
+        }
+        self.state.should_redraw = true;
+        Ok(())
+    }
+
+    /// Starts computing the histogram of the current image if it is shown,
+    /// dropping any older one.
+    fn start_histogram(&mut self) -> Result<()> {
+        self.histogram = None;
+        self.histogram_job = None;
+        if self.state.draw_histogram {
+            let pixels = self.image.pixels()?.into_owned();
+            let (window, atom) = (self.state.window.window(), self.atoms._MEVI_HISTOGRAM_READY);
+            let conn = Arc::clone(&self.shared_conn);
+            self.histogram_job = Some(Histogram::spawn(pixels, move || {
+                if let Err(e) = wake(&*conn, window, atom) {
+                    err!("Failed to report the finished histogram: {e}");
+                }
+            }));
+            info!("Computing histogram of {}", self.image.path);
+        }
+        Ok(())
+    }
+
+    fn receive_histogram(&mut self) {
+        let Some(job) = &self.histogram_job else {
+            return;
+        };
+        match job.try_recv() {
+            Ok(histogram) => {
+                info!("Computed histogram of {}", self.image.path);
+                self.histogram = Some(histogram);
+                self.histogram_job = None;
+                self.state.should_redraw = true;
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => {
+                err!("Failed to compute histogram of {}", self.image.path);
+                self.histogram_job = None;
+            }
+        }
+    }
+
+    /// Works out where each shown image goes: the pixmap it is in along with
+    /// the window and image rectangles to copy. Spreads are centred as a
+    /// group, compared images each in their own part of the window. Everything
+    /// is moved by the pan offset and cropped to the window.
+    pub fn calculate_rects(&mut self) -> Result<Vec<(Pixmap, Rect, Rect)>> {
         let attrs = self
             .conn
             .get_geometry(self.state.window.window())?
             .reply()?;
         let (parent_w, parent_h) = (attrs.width, attrs.height);
-        let (cx, cy) = (parent_w as i16 / 2, parent_h as i16 / 2);
-
-        let child_x = cx - (self.image.w as i16 / 2);
-        let child_y = cy - (self.image.h as i16 / 2);
+        self.w = parent_w;
+        self.h = parent_h;
+        // the image of the last removed file stays loaded but isn't shown
+        if self.files.len() == 0 {
+            return Ok(vec![]);
+        }
 
-        let (child_x, parent_x, child_w) = if self.image.w > parent_w {
-            (child_x.abs(), 0, parent_w)
-        } else {
-            (0, child_x, self.image.w)
-        };
-        let (child_y, parent_y, child_h) = if self.image.h > parent_h {
-            (child_y.abs(), 0, parent_h)
-        } else {
-            (0, child_y, self.image.h)
-        };
+        let window = Rect::new(0, 0, parent_w, parent_h);
+        let a = (self.state.pms.image.pixmap(), &self.image);
+        let size = |i: &MeviImage| (i.w, i.h);
 
-        let parent = Rect::new(parent_x, parent_y, parent_w, parent_h);
-        let child = Rect::new(child_x, child_y, child_w, child_h);
+        // pixmap, top left corner, size and the area it may be drawn in
+        let mut pages = vec![];
+        match (self.state.compare, &self.second) {
+            (Some(compare), Some(second)) => {
+                let b = (self.state.pms.spread_image.pixmap(), second);
+                match compare.view {
+                    CompareView::SideBySide => {
+                        let half = parent_w.saturating_sub(self.state.layout.gap) / 2;
+                        let left = Rect::new(0, 0, half, parent_h);
+                        let right = Rect::new((parent_w - half) as i16, 0, half, parent_h);
+                        for ((pixmap, image), area) in [(a, left), (b, right)] {
+                            pages.push((pixmap, area.centre(size(image)), size(image), area));
+                        }
+                    }
+                    CompareView::Wipe => {
+                        let x = compare.split_x(parent_w);
+                        let left = Rect::new(0, 0, x as u16, parent_h);
+                        let right = Rect::new(x, 0, parent_w - x as u16, parent_h);
+                        for ((pixmap, image), area) in [(a, left), (b, right)] {
+                            pages.push((pixmap, window.centre(size(image)), size(image), area));
+                        }
+                    }
+                    CompareView::Flip => {
+                        let (pixmap, image) = if compare.show_b { b } else { a };
+                        pages.push((pixmap, window.centre(size(image)), size(image), window));
+                    }
+                    CompareView::Diff => {
+                        let (pixmap, image) = match &self.diff {
+                            Some((diff, _)) => (self.state.pms.diff_image.pixmap(), diff),
+                            None => a,
+                        };
+                        pages.push((pixmap, window.centre(size(image)), size(image), window));
+                    }
+                }
+            }
+            _ => {
+                let mut spread = vec![a];
+                if let Some(second) = &self.second {
+                    spread.push((self.state.pms.spread_image.pixmap(), second));
+                }
+                if self.state.layout.rtl {
+                    spread.reverse();
+                }
 
-        self.w = parent.w;
-        self.h = parent.h;
+                let sizes = spread.iter().map(|(_, i)| size(i)).collect::<Vec<_>>();
+                let (offsets, (group_w, group_h)) = self.state.layout.arrange(&sizes);
+                let group_x = (parent_w as i32 - group_w) / 2;
+                let group_y = (parent_h as i32 - group_h) / 2;
+                for ((pixmap, image), (x, y)) in spread.into_iter().zip(offsets) {
+                    pages.push((pixmap, (group_x + x, group_y + y), size(image), window));
+                }
+            }
+        }
 
-        info!("Calculated parent draw info: {parent:?}");
-        info!("Calculated child draw info: {child:?}");
+        let (pan_x, pan_y) = self.state.pan;
+        let mut rects = vec![];
+        for (pixmap, (x, y), size, area) in pages {
+            if pixmap == self.state.pms.image.pixmap() {
+                self.origin = (x + pan_x, y + pan_y);
+            }
+            if let Some((parent, child)) = area.clip((x + pan_x, y + pan_y), size) {
+                info!("Calculated parent draw info: {parent:?}");
+                info!("Calculated child draw info: {child:?}");
+                rects.push((pixmap, parent, child));
+            }
+        }
 
-        Ok((parent, child))
+        Ok(rects)
     }
 
     fn draw_image(&mut self) -> Result<()> {
-        let (parent, child) = self.calculate_rects()?;
+        let rects = self.calculate_rects()?;
 
         self.conn.create_pixmap(
             self.screen.root_depth,
@@ -353,7 +1556,7 @@ impl<'a, C: Connection + Debug> Mevi<'a, C> {
         )?;
 
         self.fill_bg()?;
-        self.fill_back_buffer(parent, child)?;
+        self.fill_back_buffer(&rects)?;
         self.copy_to_window()?;
 
         self.conn.free_pixmap(self.state.pms.buffer.pixmap())?;
@@ -363,20 +1566,47 @@ impl<'a, C: Connection + Debug> Mevi<'a, C> {
         Ok(())
     }
 
-    pub fn fill_back_buffer(&self, parent_rect: Rect, child_rect: Rect) -> Result<()> {
-        self.conn.copy_area(
-            self.state.pms.image.pixmap(),
+    pub fn fill_back_buffer(&self, rects: &[(Pixmap, Rect, Rect)]) -> Result<()> {
+        for (pixmap, parent_rect, child_rect) in rects {
+            self.conn.copy_area(
+                *pixmap,
+                self.state.pms.buffer.pixmap(),
+                self.state.gcs.buffer.gcontext(),
+                child_rect.x,
+                child_rect.y,
+                parent_rect.x,
+                parent_rect.y,
+                child_rect.w,
+                child_rect.h,
+            )?;
+        }
+
+        self.conn.render_create_picture(
+            self.state.pics.buffer.picture(),
             self.state.pms.buffer.pixmap(),
-            self.state.gcs.buffer.gcontext(),
-            child_rect.x,
-            child_rect.y,
-            parent_rect.x,
-            parent_rect.y,
-            child_rect.w,
-            child_rect.h,
+            self.vis_info.root.pict_format,
+            &CreatePictureAux::default().repeat(Repeat::NORMAL),
         )?;
 
-        self.draw_file_info()?;
+        if self.files.len() == 0 && self.status.is_some() {
+            // e.g. a failed undo
+            self.draw_status_bar()?;
+        } else if self.files.len() == 0 {
+            self.draw_bar(
+                &RenderString::new(vec![RenderLine::new(&self.font_drawer, NO_FILES_STATUS)])
+                    .pad(5),
+            )?;
+        } else {
+            self.draw_split_line()?;
+            self.draw_crop()?;
+            self.draw_mark_indicator()?;
+            self.draw_histogram()?;
+            self.draw_file_info()?;
+            self.draw_status_bar()?;
+        }
+
+        self.conn
+            .render_free_picture(self.state.pics.buffer.picture())?;
         Ok(())
     }
 
@@ -412,15 +1642,187 @@ impl<'a, C: Connection + Debug> Mevi<'a, C> {
         Ok(())
     }
 
-    fn draw_file_info(&self) -> Result<()> {
-        if self.state.draw_info {
-            self.conn.render_create_picture(
+    fn draw_split_line(&self) -> Result<()> {
+        match self.state.compare {
+            Some(compare) if compare.view == CompareView::Wipe && self.second.is_some() => {
+                let x = compare.split_x(self.w) - (SPLIT_LINE_WIDTH / 2) as i16;
+                self.conn.render_fill_rectangles(
+                    PictOp::OVER,
+                    self.state.pics.buffer.picture(),
+                    WHITE_RENDER_COLOR,
+                    &[Rect::new(x, 0, SPLIT_LINE_WIDTH, self.h).into()],
+                )?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Shades everything but the crop selection and outlines it.
+    fn draw_crop(&self) -> Result<()> {
+        let Some(s) = self.state.crop.and_then(|c| c.selection) else {
+            return Ok(());
+        };
+        let (ox, oy) = self.origin;
+        let to_view = |v: u32, o: i32| o + (v as f32 * self.image.scale).round() as i32;
+        let (x0, y0) = (to_view(s.x, ox), to_view(s.y, oy));
+        let (x1, y1) = (to_view(s.x + s.w, ox), to_view(s.y + s.h, oy));
+        let (w, h) = (self.w as i32, self.h as i32);
+
+        // corners clamped to the window, empty rectangles are left out
+        let rect = |l: i32, t: i32, r: i32, b: i32| {
+            let (l, r) = (l.clamp(0, w), r.clamp(0, w));
+            let (t, b) = (t.clamp(0, h), b.clamp(0, h));
+            (r > l && b > t)
+                .then(|| Rect::new(l as i16, t as i16, (r - l) as u16, (b - t) as u16).into())
+        };
+        let shade = [
+            rect(0, 0, w, y0),
+            rect(0, y1, w, h),
+            rect(0, y0, x0, y1),
+            rect(x1, y0, w, y1),
+        ];
+        let border = [
+            rect(x0 - 1, y0 - 1, x1 + 1, y0),
+            rect(x0 - 1, y1, x1 + 1, y1 + 1),
+            rect(x0 - 1, y0, x0, y1),
+            rect(x1, y0, x1 + 1, y1),
+        ];
+        for (rects, color) in [
+            (shade, CROP_SHADE_RENDER_COLOR),
+            (border, WHITE_RENDER_COLOR),
+        ] {
+            let rects = rects.into_iter().flatten().collect::<Vec<_>>();
+            self.conn.render_fill_rectangles(
+                PictOp::OVER,
                 self.state.pics.buffer.picture(),
-                self.state.pms.buffer.pixmap(),
-                self.vis_info.root.pict_format,
-                &CreatePictureAux::default().repeat(Repeat::NORMAL),
+                color,
+                &rects,
             )?;
+        }
+        Ok(())
+    }
+
+    fn draw_mark_indicator(&self) -> Result<()> {
+        if self.files.is_marked() {
+            let size = MARK_INDICATOR_SIZE;
+            let x = self.w.saturating_sub(size * 2) as i16;
+            self.conn.render_fill_rectangles(
+                PictOp::OVER,
+                self.state.pics.buffer.picture(),
+                MARK_RENDER_COLOR,
+                &[Rect::new(x, size as i16, size, size).into()],
+            )?;
+        }
+        Ok(())
+    }
 
+    /// Draws the prompt if one is open, or the current status message, or
+    /// the size of the crop selection.
+    fn draw_status_bar(&self) -> Result<()> {
+        if let Some(prompt) = &self.prompt {
+            let string = prompt.to_render_string(&self.font_drawer);
+            self.draw_bar(&string)?;
+        } else if let Some(status) = &self.status {
+            let string = RenderString::new(vec![RenderLine::new(&self.font_drawer, status)]).pad(5);
+            self.draw_bar(&string)?;
+        } else if let Some(crop) = &self.state.crop {
+            let line = RenderLine::new(&self.font_drawer, crop.describe());
+            self.draw_bar(&RenderString::new(vec![line]).pad(5))?;
+        }
+        Ok(())
+    }
+
+    /// Draws `string` across the bottom of the back buffer.
+    fn draw_bar(&self, string: &RenderString) -> Result<()> {
+        let (_, h) = string.box_dimensions();
+        let w = self.w.max(1);
+
+        self.conn.create_pixmap(
+            self.screen.root_depth,
+            self.state.pms.bar_font_buffer.pixmap(),
+            self.screen.root,
+            w,
+            h,
+        )?;
+        self.conn.render_create_picture(
+            self.state.pics.bar_font_buffer.picture(),
+            self.state.pms.bar_font_buffer.pixmap(),
+            self.vis_info.root.pict_format,
+            &CreatePictureAux::default()
+                .polyedge(PolyEdge::SMOOTH)
+                .polymode(PolyMode::IMPRECISE),
+        )?;
+
+        self.font_drawer.draw(
+            *self.conn,
+            self.state.pics.bar_font_buffer.picture(),
+            self.state.pics.buffer.picture(),
+            string,
+            Some(w),
+            self.h.saturating_sub(h) as i16,
+            GRAY_RENDER_COLOR,
+        )?;
+
+        self.conn
+            .render_free_picture(self.state.pics.bar_font_buffer.picture())?;
+        self.conn
+            .free_pixmap(self.state.pms.bar_font_buffer.pixmap())?;
+        Ok(())
+    }
+
+    /// Draws the histogram on a translucent panel in the top right corner,
+    /// each channel as bars scaled to the highest count.
+    fn draw_histogram(&self) -> Result<()> {
+        let Some(histogram) = self
+            .histogram
+            .as_ref()
+            .filter(|_| self.state.draw_histogram)
+        else {
+            return Ok(());
+        };
+
+        let pad = HISTOGRAM_PADDING;
+        let (w, h) = (BINS as u16 + pad * 2, HISTOGRAM_HEIGHT + pad * 2);
+        let x = self.w.saturating_sub(w + MARK_INDICATOR_SIZE * 3) as i16;
+        let y = MARK_INDICATOR_SIZE as i16;
+        self.conn.render_fill_rectangles(
+            PictOp::OVER,
+            self.state.pics.buffer.picture(),
+            HISTOGRAM_BG_RENDER_COLOR,
+            &[Rect::new(x, y, w, h).into()],
+        )?;
+
+        let peak = histogram.peak().max(1) as u64;
+        let bottom = y + (pad + HISTOGRAM_HEIGHT) as i16;
+        for (bins, color) in [
+            (&histogram.luma, HISTOGRAM_LUMA_RENDER_COLOR),
+            (&histogram.red, HISTOGRAM_RED_RENDER_COLOR),
+            (&histogram.green, HISTOGRAM_GREEN_RENDER_COLOR),
+            (&histogram.blue, HISTOGRAM_BLUE_RENDER_COLOR),
+        ] {
+            let bars = bins
+                .iter()
+                .enumerate()
+                .filter(|(_, count)| **count > 0)
+                .map(|(i, count)| {
+                    let bar_h = (*count as u64 * HISTOGRAM_HEIGHT as u64 / peak).max(1) as u16;
+                    let bar_x = x + pad as i16 + i as i16;
+                    Rect::new(bar_x, bottom - bar_h as i16, 1, bar_h).into()
+                })
+                .collect::<Vec<_>>();
+            self.conn.render_fill_rectangles(
+                PictOp::OVER,
+                self.state.pics.buffer.picture(),
+                color,
+                &bars,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn draw_file_info(&self) -> Result<()> {
+        if self.state.draw_info {
             self.font_drawer.draw(
                 *self.conn,
                 self.state.pics.font_buffer.picture(),
@@ -430,10 +1832,33 @@ impl<'a, C: Connection + Debug> Mevi<'a, C> {
                 0,
                 GRAY_RENDER_COLOR,
             )?;
-
-            self.conn
-                .render_free_picture(self.state.pics.buffer.picture())?;
         }
         Ok(())
     }
 }
+
+/// Whether `event` still makes sense after every file was removed from the
+/// list, with only the removal left to undo.
+fn works_without_files(event: &MeviEvent) -> bool {
+    matches!(
+        event,
+        MeviEvent::DrawImage
+            | MeviEvent::ToggleFileInfo
+            | MeviEvent::ToggleFullscreen
+            | MeviEvent::Undo
+            | MeviEvent::Menu(_)
+            | MeviEvent::HistogramReady
+            | MeviEvent::Exit
+            | MeviEvent::Idle
+            | MeviEvent::Error(_)
+    )
+}
+
+/// Sends `window` a client message of type `atom`, which wakes up the event
+/// loop from another thread.
+fn wake(conn: &impl Connection, window: Window, atom: Atom) -> Result<()> {
+    let event = ClientMessageEvent::new(32, window, atom, [0u32; 5]);
+    conn.send_event(false, window, EventMask::NO_EVENT, event)?;
+    conn.flush()?;
+    Ok(())
+}
@@ -0,0 +1,140 @@
+//! Comic book archives (CBZ/ZIP and CBT/TAR). The images inside are listed
+//! in natural order and read straight from the archive when shown, nothing is
+//! extracted to disk.
+
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ArchiveError {
+    #[error("{0} is not in the archive")]
+    NoSuchMember(String),
+    #[error("The archive has no images")]
+    Empty,
+    #[error("Failed to read zip archive: {0}")]
+    Zip(#[from] zip::result::ZipError),
+}
+
+static IMAGE_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "bmp", "tif", "tiff", "tga", "ico", "pnm", "pbm", "pgm",
+    "ppm", "qoi", "avif", "exr", "hdr",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+}
+
+#[derive(Debug)]
+pub struct Archive {
+    pub path: PathBuf,
+    pub kind: ArchiveKind,
+    /// Names of the image members, in natural order.
+    pub members: Vec<String>,
+}
+
+impl Archive {
+    /// Whether `path` is an archive, judging by its extension or, failing
+    /// that, its first bytes.
+    pub fn detect(path: &Path) -> Option<ArchiveKind> {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        match ext.as_deref() {
+            Some("cbz" | "zip") => return Some(ArchiveKind::Zip),
+            Some("cbt" | "tar") => return Some(ArchiveKind::Tar),
+            _ => {}
+        }
+
+        let mut head = [0; 262];
+        let n = File::open(path).and_then(|mut f| f.read(&mut head)).ok()?;
+        if head[..n].starts_with(b"PK\x03\x04") {
+            Some(ArchiveKind::Zip)
+        } else if n == head.len() && &head[257..262] == b"ustar" {
+            Some(ArchiveKind::Tar)
+        } else {
+            None
+        }
+    }
+
+    pub fn open(path: &Path, kind: ArchiveKind) -> Result<Self> {
+        let mut members = match kind {
+            ArchiveKind::Zip => {
+                let zip = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+                zip.file_names().map(str::to_owned).collect::<Vec<_>>()
+            }
+            ArchiveKind::Tar => {
+                let mut tar = tar::Archive::new(BufReader::new(File::open(path)?));
+                let mut names = vec![];
+                for entry in tar.entries()? {
+                    let entry = entry?;
+                    if entry.header().entry_type().is_file() {
+                        names.push(entry.path()?.to_string_lossy().to_string());
+                    }
+                }
+                names
+            }
+        };
+        members.retain(|name| is_image(name));
+        members.sort_by(|a, b| natord::compare(a, b));
+        if members.is_empty() {
+            Err(ArchiveError::Empty)?;
+        }
+        info!(
+            "Opened {kind:?} archive {path:?} with {} images",
+            members.len()
+        );
+        Ok(Self {
+            path: path.to_path_buf(),
+            kind,
+            members,
+        })
+    }
+
+    /// Reads `name` from the archive into memory.
+    pub fn read(&self, name: &str) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        match self.kind {
+            ArchiveKind::Zip => {
+                let mut zip = zip::ZipArchive::new(BufReader::new(File::open(&self.path)?))?;
+                zip.by_name(name)?.read_to_end(&mut data)?;
+            }
+            ArchiveKind::Tar => {
+                let mut tar = tar::Archive::new(BufReader::new(File::open(&self.path)?));
+                let mut entry = tar
+                    .entries()?
+                    .filter_map(|e| e.ok())
+                    .find(|e| {
+                        e.path()
+                            .map(|p| p.to_string_lossy() == name)
+                            .unwrap_or(false)
+                    })
+                    .ok_or_else(|| ArchiveError::NoSuchMember(name.to_string()))?;
+                entry.read_to_end(&mut data)?;
+            }
+        }
+        info!("Read {name:?} ({} bytes) from {:?}", data.len(), self.path);
+        Ok(data)
+    }
+}
+
+fn is_image(name: &str) -> bool {
+    // skip macOS resource forks and other hidden files
+    let file_name = name.rsplit('/').next().unwrap_or(name);
+    if file_name.starts_with('.') || name.starts_with("__MACOSX/") {
+        return false;
+    }
+    Path::new(file_name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| IMAGE_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+        .unwrap_or(false)
+}
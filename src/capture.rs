@@ -0,0 +1,190 @@
+//! Grabbing the screen for `mevi --capture`. With `--region` the rectangle
+//! is picked by dragging with the mouse first, Esc cancels.
+
+use anyhow::Result;
+use image::{DynamicImage, ImageBuffer, Rgb, RgbImage};
+use thiserror::Error;
+use x11rb::{
+    connection::Connection,
+    image::{Image, PixelLayout},
+    protocol::{
+        xproto::{
+            ConnectionExt, CreateGCAux, EventMask, Gcontext, GrabMode, GrabStatus, Screen,
+            SubwindowMode, GX,
+        },
+        Event,
+    },
+    CURRENT_TIME, NONE,
+};
+
+use crate::{keys::Key, screen::PixelFormat, util::Rect};
+
+/// Crosshair glyph of the core cursor font and its mask.
+static CROSSHAIR_GLYPH: u16 = 34;
+
+#[derive(Error, Debug)]
+pub enum CaptureError {
+    #[error("Capturing needs a TrueColor or DirectColor screen")]
+    Indexed,
+    #[error("Failed to grab the {0}: {1:?}")]
+    Grab(&'static str, GrabStatus),
+    #[error("No region was selected")]
+    Cancelled,
+}
+
+/// The name a capture is saved under by default.
+pub fn file_name() -> String {
+    chrono::Local::now()
+        .format("capture-%Y%m%d-%H%M%S.png")
+        .to_string()
+}
+
+/// Reads the root window of `screen`, or the part of it picked with the
+/// mouse when `region` is set.
+pub fn grab<C: Connection>(
+    conn: &C,
+    screen: &Screen,
+    pixel_format: &PixelFormat,
+    region: bool,
+) -> Result<DynamicImage> {
+    let PixelFormat::Direct(layout) = pixel_format else {
+        return Err(CaptureError::Indexed.into());
+    };
+    let rect = if region {
+        select_region(conn, screen)?
+    } else {
+        Rect::new(0, 0, screen.width_in_pixels, screen.height_in_pixels)
+    };
+    let image = Image::get(conn, screen.root, rect.x, rect.y, rect.w, rect.h)?;
+    info!("Captured {rect:?} of the root window");
+    Ok(decode(&image, *layout))
+}
+
+/// Unpacks the pixels of `image`, keeping 16 bits per channel for deep
+/// colour screens.
+fn decode(image: &Image, layout: PixelLayout) -> DynamicImage {
+    let (w, h) = (image.width() as u32, image.height() as u32);
+    let pixel = |x: u32, y: u32| {
+        let (r, g, b) = layout.decode(image.get_pixel(x as u16, y as u16));
+        [r, g, b]
+    };
+    if layout.depth() > 24 {
+        DynamicImage::ImageRgb16(ImageBuffer::from_fn(w, h, |x, y| Rgb(pixel(x, y))))
+    } else {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(w, h, |x, y| {
+            Rgb(pixel(x, y).map(|c| (c >> 8) as u8))
+        }))
+    }
+}
+
+/// Lets the user drag out a rectangle on the screen, drawn as an inverted
+/// outline while dragging.
+fn select_region<C: Connection>(conn: &C, screen: &Screen) -> Result<Rect> {
+    let root = screen.root;
+    let font = conn.generate_id()?;
+    conn.open_font(font, b"cursor")?;
+    let cursor = conn.generate_id()?;
+    conn.create_glyph_cursor(
+        cursor,
+        font,
+        font,
+        CROSSHAIR_GLYPH,
+        CROSSHAIR_GLYPH + 1,
+        0,
+        0,
+        0,
+        0xffff,
+        0xffff,
+        0xffff,
+    )?;
+    let gc = conn.generate_id()?;
+    conn.create_gc(
+        gc,
+        root,
+        &CreateGCAux::default()
+            .function(GX::XOR)
+            .foreground(screen.white_pixel ^ screen.black_pixel)
+            .subwindow_mode(SubwindowMode::INCLUDE_INFERIORS),
+    )?;
+
+    let mask = EventMask::BUTTON_PRESS | EventMask::BUTTON_RELEASE | EventMask::POINTER_MOTION;
+    let pointer = conn
+        .grab_pointer(
+            false,
+            root,
+            mask,
+            GrabMode::ASYNC,
+            GrabMode::ASYNC,
+            NONE,
+            cursor,
+            CURRENT_TIME,
+        )?
+        .reply()?
+        .status;
+    let keyboard = conn
+        .grab_keyboard(false, root, CURRENT_TIME, GrabMode::ASYNC, GrabMode::ASYNC)?
+        .reply()?
+        .status;
+
+    let selected = match (pointer, keyboard) {
+        (GrabStatus::SUCCESS, GrabStatus::SUCCESS) => track_drag(conn, root, gc),
+        (GrabStatus::SUCCESS, status) => Err(CaptureError::Grab("keyboard", status).into()),
+        (status, _) => Err(CaptureError::Grab("pointer", status).into()),
+    };
+
+    conn.ungrab_pointer(CURRENT_TIME)?;
+    conn.ungrab_keyboard(CURRENT_TIME)?;
+    conn.free_gc(gc)?;
+    conn.free_cursor(cursor)?;
+    conn.close_font(font)?;
+    conn.flush()?;
+    selected
+}
+
+/// Follows the pointer from a press to a release of the left button.
+fn track_drag<C: Connection>(conn: &C, root: u32, gc: Gcontext) -> Result<Rect> {
+    let outline = |rect: Option<Rect>| -> Result<()> {
+        if let Some(rect) = rect {
+            conn.poly_rectangle(root, gc, &[rect.into()])?;
+            conn.flush()?;
+        }
+        Ok(())
+    };
+    let mut start = None;
+    let mut shown = None;
+    loop {
+        match conn.wait_for_event()? {
+            Event::ButtonPress(e) if e.detail == 1 => start = Some((e.root_x, e.root_y)),
+            Event::MotionNotify(e) => {
+                let Some(start) = start else {
+                    continue;
+                };
+                // drawing the same outline again erases it
+                outline(shown)?;
+                shown = Some(span(start, (e.root_x, e.root_y)));
+                outline(shown)?;
+            }
+            Event::ButtonRelease(e) if e.detail == 1 => {
+                let Some(start) = start else {
+                    continue;
+                };
+                outline(shown)?;
+                let rect = span(start, (e.root_x, e.root_y));
+                if rect.w == 0 || rect.h == 0 {
+                    return Err(CaptureError::Cancelled.into());
+                }
+                return Ok(rect);
+            }
+            Event::KeyPress(e) if Key::from(e.detail) == Key::Esc => {
+                outline(shown)?;
+                return Err(CaptureError::Cancelled.into());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The rectangle between two corners.
+fn span((x0, y0): (i16, i16), (x1, y1): (i16, i16)) -> Rect {
+    Rect::new(x0.min(x1), y0.min(y1), x0.abs_diff(x1), y0.abs_diff(y1))
+}
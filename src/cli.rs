@@ -1,9 +1,29 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use std::path::PathBuf;
 
+use crate::{
+    compare::CompareView, diff::DiffStyle, export::ExportOptions, img::tonemap::ToneMap,
+    wallpaper::WallpaperMode,
+};
+
+/// Exit code used with `--print-marked` when no files were marked.
+pub static NOTHING_MARKED_EXIT_CODE: i32 = 3;
+/// Exit code of `mevi diff` when the images don't match.
+pub static DIFF_MISMATCH_EXIT_CODE: i32 = 1;
+/// Exit code of `mevi diff` when an image couldn't be read or the heatmap
+/// couldn't be written.
+pub static DIFF_ERROR_EXIT_CODE: i32 = 2;
+/// Exit code of `mevi export` when any file couldn't be converted.
+pub static EXPORT_ERROR_EXIT_CODE: i32 = 2;
+
+/// A lightweight X11 media viewer
 #[derive(Parser)]
+#[command(subcommand_negates_reqs = true)]
 pub struct Cli {
-    pub path: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[arg(required_unless_present_any = ["capture", "region"])]
+    pub paths: Vec<PathBuf>,
     #[arg(long, required = false, help = "Print debug information")]
     pub debug: bool,
     #[arg(
@@ -13,6 +33,194 @@ pub struct Cli {
         help = "Display image information in window"
     )]
     pub info: bool,
+    #[arg(long, required = false, help = "Display the histogram in window")]
+    pub histogram: bool,
     #[arg(long, short, required = false, help = "Start Mevi in fullscreen mode")]
     pub fullscreen: bool,
+    #[arg(
+        long,
+        short = 'o',
+        required = false,
+        help = "Print marked file paths to stdout on exit, leaving out files inside archives and unsaved captures"
+    )]
+    pub print_marked: bool,
+    #[arg(
+        long,
+        short,
+        required = false,
+        help = "Destination directory for copying and moving files"
+    )]
+    pub destination: Option<PathBuf>,
+    #[arg(
+        long,
+        required = false,
+        help = "Tone mapping operator for high bit depth images [default: reinhard for floating point images, clip otherwise]"
+    )]
+    pub tonemap: Option<ToneMap>,
+    #[arg(
+        long,
+        short,
+        required = false,
+        default_value_t = 0.0,
+        allow_negative_numbers = true,
+        help = "Exposure adjustment in stops"
+    )]
+    pub exposure: f32,
+    #[arg(
+        long,
+        required = false,
+        help = "Show two files side by side, like the pages of a book"
+    )]
+    pub spread: bool,
+    #[arg(
+        long,
+        required = false,
+        help = "Read right to left: swaps the arrow keys and the pages of a spread"
+    )]
+    pub rtl: bool,
+    #[arg(
+        long,
+        required = false,
+        help = "Show the first file on its own in spreads, like a book cover"
+    )]
+    pub cover: bool,
+    #[arg(
+        long,
+        required = false,
+        default_value_t = 0,
+        help = "Space between the pages of a spread in pixels"
+    )]
+    pub spread_gap: u16,
+    #[arg(
+        long,
+        required = false,
+        help = "Compare each file with the one after it"
+    )]
+    pub compare: bool,
+    #[arg(
+        long,
+        required = false,
+        default_value = "side-by-side",
+        help = "How to show compared files"
+    )]
+    pub compare_view: CompareView,
+    #[arg(
+        long,
+        required = false,
+        default_value = "heatmap",
+        help = "How to show the differences in the diff view"
+    )]
+    pub diff_style: DiffStyle,
+    #[arg(
+        long,
+        required = false,
+        default_value_t = 0,
+        help = "Channel difference up to which pixels count as unchanged"
+    )]
+    pub diff_threshold: u8,
+    #[arg(
+        long,
+        required = false,
+        help = "ICC profile of the monitor [default: the _ICC_PROFILE root window property, or sRGB]"
+    )]
+    pub monitor_profile: Option<PathBuf>,
+    #[arg(
+        long,
+        required = false,
+        conflicts_with = "paths",
+        help = "Open a capture of the screen instead of files"
+    )]
+    pub capture: bool,
+    #[arg(
+        long,
+        required = false,
+        conflicts_with = "paths",
+        help = "Open a capture of a rectangle selected with the mouse"
+    )]
+    pub region: bool,
+    #[command(flatten)]
+    pub export: ExportOptions,
+    #[arg(
+        long,
+        required = false,
+        default_value = "fill",
+        help = "How \"Set as wallpaper\" lays the image out on the screen"
+    )]
+    pub wallpaper_mode: WallpaperMode,
+    #[cfg(feature = "raw")]
+    #[arg(
+        long,
+        required = false,
+        help = "Demosaic camera RAW data instead of showing the embedded preview"
+    )]
+    pub raw_demosaic: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Compare two images without opening a window. Exits with 0 when they
+    /// match, 1 when they don't and 2 on errors.
+    Diff(DiffArgs),
+    /// Convert images to another format without opening a window, writing
+    /// each next to the original unless an output directory is given.
+    Export(ExportArgs),
+}
+
+#[derive(Args)]
+pub struct DiffArgs {
+    pub a: PathBuf,
+    pub b: PathBuf,
+    #[arg(
+        long,
+        short,
+        required = false,
+        default_value_t = 0,
+        help = "Channel difference up to which pixels count as unchanged"
+    )]
+    pub threshold: u8,
+    #[arg(
+        long,
+        required = false,
+        default_value_t = 0,
+        help = "Number of changed pixels still counted as a match"
+    )]
+    pub max_changed: u64,
+    #[arg(
+        long,
+        short,
+        required = false,
+        help = "Write the differences to this file"
+    )]
+    pub output: Option<PathBuf>,
+    #[arg(
+        long,
+        required = false,
+        default_value = "heatmap",
+        help = "How to draw the differences"
+    )]
+    pub style: DiffStyle,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+    #[arg(
+        long,
+        short,
+        required = true,
+        help = "Extension of the format to write, e.g. png, jpg or webp"
+    )]
+    pub format: String,
+    #[arg(
+        long,
+        short,
+        required = false,
+        help = "Directory to write the converted files to"
+    )]
+    pub output: Option<PathBuf>,
+    #[arg(long, required = false, help = "Replace files that already exist")]
+    pub overwrite: bool,
+    #[command(flatten)]
+    pub options: ExportOptions,
 }
@@ -0,0 +1,95 @@
+//! Comparing the current file (A) with the one after it (B). Both are shown at
+//! the same scale and pan, either next to each other, split by a wipe line
+//! that can be dragged across the window, one at a time, or as a map of the
+//! pixels that differ.
+
+use clap::ValueEnum;
+
+use crate::{diff::DiffStyle, CLI};
+
+/// How close to the wipe line a drag has to start to move it, in pixels.
+pub static SPLIT_GRAB_DISTANCE: i16 = 8;
+pub static SPLIT_LINE_WIDTH: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CompareView {
+    /// A on the left, B on the right
+    SideBySide,
+    /// A left of the split line, B right of it
+    Wipe,
+    /// A or B, flipped with a key
+    Flip,
+    /// The pixels that differ between A and B
+    Diff,
+}
+
+impl CompareView {
+    pub fn next(self) -> Self {
+        match self {
+            CompareView::SideBySide => CompareView::Wipe,
+            CompareView::Wipe => CompareView::Flip,
+            CompareView::Flip => CompareView::Diff,
+            CompareView::Diff => CompareView::SideBySide,
+        }
+    }
+}
+
+impl std::fmt::Display for CompareView {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            CompareView::SideBySide => "side by side",
+            CompareView::Wipe => "wipe",
+            CompareView::Flip => "flip",
+            CompareView::Diff => "diff",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Compare {
+    pub view: CompareView,
+    /// Whether B is shown in the flip view.
+    pub show_b: bool,
+    /// Position of the wipe line as a fraction of the window width.
+    pub split: f32,
+    pub diff_style: DiffStyle,
+}
+
+impl Compare {
+    pub fn new(view: CompareView) -> Self {
+        Self {
+            view,
+            show_b: false,
+            split: 0.5,
+            diff_style: CLI.diff_style,
+        }
+    }
+
+    /// Shows the other image, switching to the flip view first if needed.
+    pub fn flip(&mut self) {
+        if self.view == CompareView::Flip {
+            self.show_b = !self.show_b;
+        } else {
+            self.view = CompareView::Flip;
+            self.show_b = true;
+        }
+    }
+
+    pub fn split_x(&self, w: u16) -> i16 {
+        (w as f32 * self.split).round() as i16
+    }
+
+    pub fn set_split_x(&mut self, x: i16, w: u16) {
+        self.split = (x as f32 / w.max(1) as f32).clamp(0.0, 1.0);
+    }
+
+    pub fn describe(&self) -> String {
+        match self.view {
+            CompareView::Flip if self.show_b => "flip, showing B".into(),
+            CompareView::Flip => "flip, showing A".into(),
+            CompareView::Diff => format!("diff ({})", self.diff_style),
+            view => view.to_string(),
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! Selecting part of the image to save. Selections are kept in image pixels
+//! so they stay put when zooming or panning.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aspect {
+    Free,
+    /// The aspect ratio of the image itself.
+    Original,
+    Square,
+    FourThree,
+    ThreeTwo,
+    SixteenNine,
+}
+
+impl Aspect {
+    pub fn next(self) -> Self {
+        match self {
+            Aspect::Free => Aspect::Original,
+            Aspect::Original => Aspect::Square,
+            Aspect::Square => Aspect::FourThree,
+            Aspect::FourThree => Aspect::ThreeTwo,
+            Aspect::ThreeTwo => Aspect::SixteenNine,
+            Aspect::SixteenNine => Aspect::Free,
+        }
+    }
+
+    /// Width over height, if the aspect is fixed.
+    fn ratio(self, (ow, oh): (u32, u32)) -> Option<f32> {
+        match self {
+            Aspect::Free => None,
+            Aspect::Original => Some(ow as f32 / oh.max(1) as f32),
+            Aspect::Square => Some(1.0),
+            Aspect::FourThree => Some(4.0 / 3.0),
+            Aspect::ThreeTwo => Some(3.0 / 2.0),
+            Aspect::SixteenNine => Some(16.0 / 9.0),
+        }
+    }
+}
+
+impl std::fmt::Display for Aspect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Aspect::Free => "free",
+            Aspect::Original => "original",
+            Aspect::Square => "1:1",
+            Aspect::FourThree => "4:3",
+            Aspect::ThreeTwo => "3:2",
+            Aspect::SixteenNine => "16:9",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// A rectangle in image pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Crop {
+    pub aspect: Aspect,
+    pub selection: Option<Selection>,
+    /// Where the current drag started.
+    anchor: (u32, u32),
+    /// Size of the image being cropped.
+    bounds: (u32, u32),
+}
+
+impl Crop {
+    pub fn new(bounds: (u32, u32)) -> Self {
+        Self {
+            aspect: Aspect::Free,
+            selection: None,
+            anchor: (0, 0),
+            bounds,
+        }
+    }
+
+    pub fn start(&mut self, point: (u32, u32)) {
+        self.anchor = point;
+        self.selection = None;
+    }
+
+    /// Spans the selection from the anchor to `point`, shrinking it to the
+    /// aspect ratio and the image if needed.
+    pub fn drag_to(&mut self, (px, py): (u32, u32)) {
+        let (ax, ay) = self.anchor;
+        let (bw, bh) = self.bounds;
+        let (mut w, mut h) = (px.abs_diff(ax), py.abs_diff(ay));
+        if let Some(ratio) = self.aspect.ratio(self.bounds) {
+            // follow the side that was dragged further
+            if w as f32 / ratio >= h as f32 {
+                h = (w as f32 / ratio).round() as u32;
+            } else {
+                w = (h as f32 * ratio).round() as u32;
+            }
+            // room left in the direction of the drag
+            let room_w = if px < ax { ax } else { bw - ax };
+            let room_h = if py < ay { ay } else { bh - ay };
+            if w > room_w || h > room_h {
+                let fit = (room_w as f32 / w as f32).min(room_h as f32 / h as f32);
+                w = (w as f32 * fit) as u32;
+                h = (h as f32 * fit) as u32;
+            }
+        }
+        let x = if px < ax { ax - w } else { ax };
+        let y = if py < ay { ay - h } else { ay };
+        self.selection = (w > 0 && h > 0).then_some(Selection { x, y, w, h });
+    }
+
+    /// Moves the selection by `(dx, dy)` pixels, or grows and shrinks it when
+    /// `resize` is set, keeping it inside the image.
+    pub fn nudge(&mut self, (dx, dy): (i32, i32), resize: bool) {
+        let (bw, bh) = self.bounds;
+        let ratio = self.aspect.ratio(self.bounds);
+        let Some(s) = &mut self.selection else {
+            return;
+        };
+        let offset = |v: u32, d: i32| (v as i64 + d as i64).max(0) as u32;
+        if resize {
+            let (mut w, mut h) = (offset(s.w, dx).max(1), offset(s.h, dy).max(1));
+            match ratio {
+                Some(ratio) if dx != 0 => h = ((w as f32 / ratio).round() as u32).max(1),
+                Some(ratio) => w = ((h as f32 * ratio).round() as u32).max(1),
+                None => {}
+            }
+            if s.x + w <= bw && s.y + h <= bh {
+                (s.w, s.h) = (w, h);
+            }
+        } else {
+            s.x = offset(s.x, dx).min(bw - s.w);
+            s.y = offset(s.y, dy).min(bh - s.h);
+        }
+    }
+
+    /// Switches to the next aspect ratio, fitting the selection to it from its
+    /// top left corner.
+    pub fn next_aspect(&mut self) {
+        self.aspect = self.aspect.next();
+        let (Some(ratio), Some(s)) = (self.aspect.ratio(self.bounds), self.selection) else {
+            return;
+        };
+        let h = (s.w as f32 / ratio).round() as u32;
+        self.anchor = (s.x, s.y);
+        self.drag_to((s.x + s.w, (s.y + h).min(self.bounds.1)));
+    }
+
+    pub fn describe(&self) -> String {
+        match self.selection {
+            Some(s) => format!(
+                "crop: {}x{} at {},{} (aspect {}), enter to save",
+                s.w, s.h, s.x, s.y, self.aspect
+            ),
+            None => format!("crop: drag to select (aspect {})", self.aspect),
+        }
+    }
+}
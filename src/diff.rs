@@ -0,0 +1,233 @@
+//! Per-pixel differences between two images of the same size, used by the
+//! diff view of the comparison and by `mevi diff`.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use image::{DynamicImage, Rgb, RgbImage};
+use thiserror::Error;
+
+use crate::{
+    cli::{DiffArgs, DIFF_ERROR_EXIT_CODE, DIFF_MISMATCH_EXIT_CODE},
+    img,
+};
+
+#[derive(Error, Debug)]
+pub enum DiffError {
+    #[error("Images differ in size: {0}x{1} and {2}x{3}")]
+    SizeMismatch(u32, u32, u32, u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DiffStyle {
+    /// Colour every pixel by how much it changed
+    Heatmap,
+    /// Highlight changed pixels over a dimmed copy of the first image
+    Mask,
+}
+
+impl DiffStyle {
+    pub fn next(self) -> Self {
+        match self {
+            DiffStyle::Heatmap => DiffStyle::Mask,
+            DiffStyle::Mask => DiffStyle::Heatmap,
+        }
+    }
+}
+
+impl std::fmt::Display for DiffStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DiffStyle::Heatmap => "heatmap",
+            DiffStyle::Mask => "mask",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Heatmap colours from no change to the largest change.
+static HEATMAP: [[f32; 3]; 5] = [
+    [0.0, 0.0, 0.0],
+    [0.0, 0.0, 255.0],
+    [255.0, 0.0, 0.0],
+    [255.0, 255.0, 0.0],
+    [255.0, 255.0, 255.0],
+];
+
+static MASK_COLOR: Rgb<u8> = Rgb([255, 0, 255]);
+
+#[derive(Debug, Clone, Copy)]
+pub struct DiffStats {
+    /// Largest difference of any channel.
+    pub max: u8,
+    /// Mean absolute difference per channel.
+    pub mean: f64,
+    /// Peak signal-to-noise ratio in dB, infinite for identical images.
+    pub psnr: f64,
+    /// Pixels with a channel differing by more than the threshold.
+    pub changed: u64,
+    pub pixels: u64,
+}
+
+impl DiffStats {
+    pub fn summary(&self) -> Vec<String> {
+        let psnr = if self.psnr.is_finite() {
+            format!("{:.2} dB", self.psnr)
+        } else {
+            "inf".into()
+        };
+        let percent = self.changed as f64 * 100.0 / self.pixels.max(1) as f64;
+        vec![
+            format!("max error: {}", self.max),
+            format!("mean error: {:.3}", self.mean),
+            format!("PSNR: {psnr}"),
+            format!(
+                "changed pixels: {}/{} ({percent:.2}%)",
+                self.changed, self.pixels
+            ),
+        ]
+    }
+}
+
+pub struct Diff {
+    pub width: u32,
+    pub height: u32,
+    /// Largest channel difference of each pixel.
+    errors: Vec<u8>,
+    pub threshold: u8,
+    pub stats: DiffStats,
+}
+
+impl Diff {
+    /// Compares `a` and `b` at 8 bits per channel. Alpha only counts when
+    /// either image has it.
+    pub fn compute(a: &DynamicImage, b: &DynamicImage, threshold: u8) -> Result<Self, DiffError> {
+        let ((aw, ah), (bw, bh)) = ((a.width(), a.height()), (b.width(), b.height()));
+        if (aw, ah) != (bw, bh) {
+            return Err(DiffError::SizeMismatch(aw, ah, bw, bh));
+        }
+
+        let channels = if a.color().has_alpha() || b.color().has_alpha() {
+            4
+        } else {
+            3
+        };
+        let (a, b) = (a.to_rgba8(), b.to_rgba8());
+
+        let mut errors = Vec::with_capacity((aw * ah) as usize);
+        let (mut max, mut changed) = (0, 0);
+        let (mut sum, mut squares) = (0u64, 0u64);
+        for (pa, pb) in a.pixels().zip(b.pixels()) {
+            let mut error = 0;
+            for c in 0..channels {
+                let d = pa.0[c].abs_diff(pb.0[c]);
+                sum += d as u64;
+                squares += d as u64 * d as u64;
+                error = error.max(d);
+            }
+            max = max.max(error);
+            if error > threshold {
+                changed += 1;
+            }
+            errors.push(error);
+        }
+
+        let samples = (errors.len() * channels).max(1) as f64;
+        let mse = squares as f64 / samples;
+        let psnr = if mse == 0.0 {
+            f64::INFINITY
+        } else {
+            10.0 * (255.0 * 255.0 / mse).log10()
+        };
+        let stats = DiffStats {
+            max,
+            mean: sum as f64 / samples,
+            psnr,
+            changed,
+            pixels: errors.len() as u64,
+        };
+        info!("Compared {aw}x{ah} images: {stats:?}");
+
+        Ok(Self {
+            width: aw,
+            height: ah,
+            errors,
+            threshold,
+            stats,
+        })
+    }
+
+    /// Draws the differences in `style`. The heatmap is scaled to the largest
+    /// difference so small ones stay visible, the mask is drawn over `base`.
+    pub fn render(&self, style: DiffStyle, base: &DynamicImage) -> RgbImage {
+        match style {
+            DiffStyle::Heatmap => {
+                let max = self.stats.max.max(1) as f32;
+                self.map(|_, _, e| heat(e as f32 / max))
+            }
+            DiffStyle::Mask => {
+                let base = base.to_luma8();
+                self.map(|x, y, e| {
+                    if e > self.threshold {
+                        MASK_COLOR
+                    } else {
+                        let v = base.get_pixel(x, y).0[0] / 3;
+                        Rgb([v, v, v])
+                    }
+                })
+            }
+        }
+    }
+
+    fn map(&self, f: impl Fn(u32, u32, u8) -> Rgb<u8>) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            f(x, y, self.errors[(y * self.width + x) as usize])
+        })
+    }
+}
+
+/// Runs `mevi diff`: compares the two images, prints the statistics and
+/// optionally writes the differences. Returns the exit code.
+pub fn run(args: &DiffArgs) -> i32 {
+    match compare_files(args) {
+        Ok(true) => 0,
+        Ok(false) => DIFF_MISMATCH_EXIT_CODE,
+        Err(e) => {
+            err!("{e}");
+            DIFF_ERROR_EXIT_CODE
+        }
+    }
+}
+
+/// Whether the images of `args` match.
+fn compare_files(args: &DiffArgs) -> Result<bool> {
+    let load =
+        |path: &Path| img::decode_file(path).map_err(|e| anyhow!("Failed to load {path:?}: {e}"));
+    let (a, b) = (load(&args.a)?, load(&args.b)?);
+    let diff = match Diff::compute(&a, &b, args.threshold) {
+        Ok(diff) => diff,
+        Err(e) => {
+            println!("{e}");
+            return Ok(false);
+        }
+    };
+
+    for line in diff.stats.summary() {
+        println!("{line}");
+    }
+    if let Some(output) = &args.output {
+        diff.render(args.style, &a).save(output)?;
+        info!("Wrote the differences to {output:?}");
+    }
+    Ok(diff.stats.changed <= args.max_changed)
+}
+
+/// Interpolates the heatmap at `t` in 0..=1.
+fn heat(t: f32) -> Rgb<u8> {
+    let pos = t.clamp(0.0, 1.0) * (HEATMAP.len() - 1) as f32;
+    let i = (pos as usize).min(HEATMAP.len() - 2);
+    let f = pos - i as f32;
+    let (from, to) = (HEATMAP[i], HEATMAP[i + 1]);
+    Rgb([0, 1, 2].map(|c| (from[c] + (to[c] - from[c]) * f).round() as u8))
+}
@@ -1,15 +1,68 @@
-use crate::{app::Mevi, keys::Key};
+use crate::{
+    app::Mevi,
+    img::adjust::{Adjustment, BRIGHTNESS_STEP, CONTRAST_STEP, GAMMA_STEP, SATURATION_STEP},
+    keys::Key,
+    prompt::PromptEvent,
+};
 use x11rb::{
     connection::Connection,
-    protocol::{xproto::Rectangle, Event},
+    protocol::{
+        xproto::{KeyButMask, Rectangle},
+        Event,
+    },
     x11_utils::X11Error,
 };
 
 pub enum MeviEvent {
     DrawImage,
     ToggleFileInfo,
+    ToggleHistogram,
     ToggleFullscreen,
+    ZoomIn,
+    ZoomOut,
+    ZoomFit,
+    ZoomActual,
+    NextToneMap,
+    ExposureUp,
+    ExposureDown,
+    ToggleColorManagement,
+    NextChannel,
+    Adjust(Adjustment),
+    NextFile,
+    PrevFile,
+    ToggleSpread,
+    ToggleReadingDirection,
+    ToggleCompare,
+    NextCompareView,
+    FlipCompare,
+    NextDiffStyle,
+    DragStart(i16, i16),
+    Drag(i16, i16),
+    DragEnd,
+    ToggleCrop,
+    NextCropAspect,
+    /// Moves the crop selection, or resizes it when set.
+    NudgeCrop(i32, i32, bool),
+    SaveCrop,
+    SaveAs,
+    Resize,
+    NextPage,
+    PrevPage,
+    ToggleMark,
+    MarkAll,
+    UnmarkAll,
+    InvertMarks,
+    Trash,
+    Rename,
+    CopyToDestination,
+    MoveToDestination,
+    Undo,
+    Prompt(PromptEvent),
+    KeyHandlerPrefix,
+    KeyHandler(String),
+    KeyHandlerCancel,
     Menu(MenuEvent),
+    HistogramReady,
     Exit,
     Idle,
     Error(X11Error),
@@ -31,9 +84,105 @@ impl MeviEvent {
         event!(evt);
         match evt {
             Event::Expose(e) if e.count == 0 => Self::DrawImage,
+            Event::KeyRelease(e) if app.prompt.is_some() => match Key::from(e.detail) {
+                Key::Enter => Self::Prompt(PromptEvent::Submit),
+                Key::Esc => Self::Prompt(PromptEvent::Cancel),
+                Key::Backspace => Self::Prompt(PromptEvent::Backspace),
+                _ => match app.keymap.char_for(e.detail, e.state) {
+                    Some(c) => Self::Prompt(PromptEvent::Input(c)),
+                    None => Self::Idle,
+                },
+            },
+            Event::KeyRelease(e) if app.state.awaiting_handler_key => {
+                let ctrl = u16::from(e.state) & u16::from(KeyButMask::CONTROL) != 0;
+                match Key::from(e.detail) {
+                    // modifier releases end up here, keep waiting for a real key
+                    Key::Unknown => Self::Idle,
+                    Key::Esc => Self::KeyHandlerCancel,
+                    key if ctrl => Self::KeyHandler(format!("C-{key}")),
+                    key => Self::KeyHandler(key.to_string()),
+                }
+            }
+            Event::KeyRelease(e) if u16::from(e.state) & u16::from(KeyButMask::CONTROL) != 0 => {
+                // display adjustments, shift lowers the value
+                let shift = u16::from(e.state) & u16::from(KeyButMask::SHIFT) != 0;
+                let sign = if shift { -1.0 } else { 1.0 };
+                match Key::from(e.detail) {
+                    Key::X => Self::KeyHandlerPrefix,
+                    Key::Z => Self::Undo,
+                    Key::B => Self::Adjust(Adjustment::Brightness(sign * BRIGHTNESS_STEP)),
+                    Key::C => Self::Adjust(Adjustment::Contrast(sign * CONTRAST_STEP)),
+                    Key::G => Self::Adjust(Adjustment::Gamma(sign * GAMMA_STEP)),
+                    Key::S => Self::Adjust(Adjustment::Saturation(sign * SATURATION_STEP)),
+                    Key::I => Self::Adjust(Adjustment::Invert),
+                    Key::M => Self::Adjust(Adjustment::Grayscale),
+                    Key::R => Self::Adjust(Adjustment::Reset),
+                    _ => Self::Idle,
+                }
+            }
+            Event::KeyRelease(e) if app.state.crop.is_some() && !app.menu.visible => {
+                // arrows nudge the selection, shift resizes it
+                let resize = u16::from(e.state) & u16::from(KeyButMask::SHIFT) != 0;
+                match Key::from(e.detail) {
+                    Key::Right => Self::NudgeCrop(1, 0, resize),
+                    Key::Left => Self::NudgeCrop(-1, 0, resize),
+                    Key::Up => Self::NudgeCrop(0, -1, resize),
+                    Key::Down => Self::NudgeCrop(0, 1, resize),
+                    Key::Tab => Self::NextCropAspect,
+                    Key::Enter => Self::SaveCrop,
+                    Key::S if resize => Self::Resize,
+                    Key::S => Self::SaveAs,
+                    Key::X | Key::Esc => Self::ToggleCrop,
+                    Key::M => {
+                        let x = (app.w / 2).saturating_sub(menu_rect.width / 2);
+                        let y = (app.h / 2).saturating_sub(menu_rect.height / 2);
+                        Self::Menu(MenuEvent::MapAt(x as i16, y as i16))
+                    }
+                    Key::Equal => Self::ZoomIn,
+                    Key::Minus => Self::ZoomOut,
+                    Key::Num0 => Self::ZoomFit,
+                    Key::Num1 => Self::ZoomActual,
+                    _ => Self::Idle,
+                }
+            }
             Event::KeyRelease(e) => match Key::from(e.detail) {
+                Key::X => Self::ToggleCrop,
+                Key::S if u16::from(e.state) & u16::from(KeyButMask::SHIFT) != 0 => Self::Resize,
+                Key::S => Self::SaveAs,
                 Key::F => Self::ToggleFullscreen,
                 Key::I => Self::ToggleFileInfo,
+                Key::G => Self::ToggleHistogram,
+                Key::Equal => Self::ZoomIn,
+                Key::Minus => Self::ZoomOut,
+                Key::Num0 => Self::ZoomFit,
+                Key::Num1 => Self::ZoomActual,
+                Key::T => Self::NextToneMap,
+                Key::E if u16::from(e.state) & u16::from(KeyButMask::SHIFT) != 0 => {
+                    Self::ExposureDown
+                }
+                Key::E => Self::ExposureUp,
+                Key::C => Self::ToggleColorManagement,
+                Key::L => Self::NextChannel,
+                Key::Right if app.state.layout.rtl => Self::PrevFile,
+                Key::Left if app.state.layout.rtl => Self::NextFile,
+                Key::Right | Key::N => Self::NextFile,
+                Key::Left | Key::P => Self::PrevFile,
+                Key::D => Self::ToggleSpread,
+                Key::R => Self::ToggleReadingDirection,
+                Key::K => Self::ToggleCompare,
+                Key::W => Self::NextCompareView,
+                Key::B => Self::FlipCompare,
+                Key::H => Self::NextDiffStyle,
+                Key::PageDown => Self::NextPage,
+                Key::PageUp => Self::PrevPage,
+                Key::Space => Self::ToggleMark,
+                Key::A => Self::MarkAll,
+                Key::U => Self::UnmarkAll,
+                Key::V => Self::InvertMarks,
+                Key::Delete => Self::Trash,
+                Key::F2 => Self::Rename,
+                Key::F5 => Self::CopyToDestination,
+                Key::F6 => Self::MoveToDestination,
                 Key::M if !app.menu.visible => {
                     let x = (app.w / 2).saturating_sub(menu_rect.width / 2);
                     let y = (app.h / 2).saturating_sub(menu_rect.height / 2);
@@ -58,6 +207,8 @@ impl MeviEvent {
                     Self::Menu(MenuEvent::Select)
                 } else if (e.detail == 1 || e.detail == 3) && app.menu.visible {
                     Self::Menu(MenuEvent::Unmap)
+                } else if e.detail == 1 {
+                    Self::DragStart(e.event_x, e.event_y)
                 } else {
                     Self::Idle
                 }
@@ -67,10 +218,13 @@ impl MeviEvent {
                     Self::Menu(MenuEvent::FindHovered(e.event_x, e.event_y))
                 } else if app.menu.visible {
                     Self::Menu(MenuEvent::Deselect)
+                } else if app.state.drag.is_some() {
+                    Self::Drag(e.event_x, e.event_y)
                 } else {
                     Self::Idle
                 }
             }
+            Event::ButtonRelease(e) if e.detail == 1 && app.state.drag.is_some() => Self::DragEnd,
             Event::ClientMessage(e) => {
                 let data = e.data.as_data32();
                 if e.format == 32
@@ -79,6 +233,11 @@ impl MeviEvent {
                 {
                     return Self::Exit;
                 }
+                if e.window == app.state.window.window()
+                    && e.type_ == app.atoms._MEVI_HISTOGRAM_READY
+                {
+                    return Self::HistogramReady;
+                }
                 Self::Idle
             }
             Event::Error(e) => Self::Error(e),
@@ -0,0 +1,96 @@
+use std::{
+    env,
+    fs::{self, Metadata},
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    time::SystemTime,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ExecError {
+    #[error("Could not determine the config directory")]
+    NoConfigDir,
+    #[error("No executable key handler found at {0:?}")]
+    NotFound(PathBuf),
+    #[error("Failed to run key handler: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Key handler exited with {0}")]
+    Status(ExitStatus),
+}
+
+/// `$XDG_CONFIG_HOME/mevi`, falling back to `$HOME/.config/mevi`.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(base.join("mevi"))
+}
+
+pub fn key_handler_path() -> Result<PathBuf, ExecError> {
+    let path = config_dir()
+        .ok_or(ExecError::NoConfigDir)?
+        .join("exec")
+        .join("key-handler");
+    if path.is_file() {
+        Ok(path)
+    } else {
+        Err(ExecError::NotFound(path))
+    }
+}
+
+/// Runs the key handler script with `key` as its only argument and `paths`
+/// written to its stdin, one per line.
+/// Returns the paths that were changed or removed by the script.
+pub fn run_key_handler(key: &str, paths: &[PathBuf]) -> Result<Vec<PathBuf>, ExecError> {
+    let handler = key_handler_path()?;
+    info!("Running key handler {handler:?} with key {key}");
+
+    let before: Vec<Option<FileStamp>> = paths.iter().map(|p| FileStamp::of(p)).collect();
+
+    let mut child = Command::new(&handler)
+        .arg(key)
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // the script may exit or close stdin without reading every path, it
+        // still has to be waited for
+        let written = paths
+            .iter()
+            .try_for_each(|path| writeln!(stdin, "{}", path.display()));
+        if let Err(e) = written {
+            info!("Key handler stopped reading its input: {e}");
+        }
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(ExecError::Status(status));
+    }
+
+    let changed = paths
+        .iter()
+        .zip(before)
+        .filter_map(|(path, stamp)| (FileStamp::of(path) != stamp).then(|| path.clone()))
+        .collect::<Vec<_>>();
+    info!("Key handler finished, changed files: {changed:?}");
+    Ok(changed)
+}
+
+#[derive(PartialEq, Eq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<Self> {
+        fs::metadata(path).ok().map(|m: Metadata| Self {
+            modified: m.modified().ok(),
+            len: m.len(),
+        })
+    }
+}
@@ -0,0 +1,265 @@
+//! Writing images to disk, for "Save as" in the window and `mevi export`.
+
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use clap::{Args, ValueEnum};
+use image::{
+    codecs::{
+        hdr::HdrEncoder,
+        jpeg::JpegEncoder,
+        png::{self, PngEncoder},
+    },
+    imageops::FilterType,
+    ColorType, DynamicImage, ImageEncoder, ImageFormat,
+};
+use thiserror::Error;
+
+use crate::{
+    cli::{ExportArgs, EXPORT_ERROR_EXIT_CODE},
+    img::{self, icc, tonemap},
+};
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("Unknown image format: {0:?}")]
+    UnknownFormat(String),
+    #[error("Saving {0:?} images is not supported")]
+    Unsupported(String),
+    #[error("WebP images are saved lossless, --quality only applies to JPEG")]
+    LosslessWebp,
+    #[error("{0} already exists")]
+    Exists(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PngCompression {
+    Fast,
+    Default,
+    Best,
+}
+
+impl From<PngCompression> for png::CompressionType {
+    fn from(compression: PngCompression) -> Self {
+        match compression {
+            PngCompression::Fast => png::CompressionType::Fast,
+            PngCompression::Default => png::CompressionType::Default,
+            PngCompression::Best => png::CompressionType::Best,
+        }
+    }
+}
+
+/// Encoder settings shared by the window and `mevi export`.
+#[derive(Args, Debug, Clone, Copy)]
+#[group(skip)]
+pub struct ExportOptions {
+    #[arg(
+        long,
+        short,
+        required = false,
+        value_parser = clap::value_parser!(u8).range(1..=100),
+        help = "JPEG quality when saving images, from 1 to 100 [default: 90]. Not accepted for WebP, which is saved lossless"
+    )]
+    pub quality: Option<u8>,
+    #[arg(
+        long,
+        required = false,
+        default_value = "default",
+        help = "PNG compression level when saving images"
+    )]
+    pub png_compression: PngCompression,
+}
+
+const DEFAULT_QUALITY: u8 = 90;
+
+/// Icons can't be wider or taller than this.
+const ICO_MAX_SIZE: u32 = 256;
+
+/// The format named by the extension `ext`, e.g. "jpg".
+pub fn format_for(ext: &str) -> Result<ImageFormat, ExportError> {
+    // the PNM encoder only writes bilevel images that are already bilevel
+    if ext.eq_ignore_ascii_case("pbm") {
+        return Err(ExportError::Unsupported(ext.into()));
+    }
+    ImageFormat::from_extension(ext).ok_or_else(|| ExportError::UnknownFormat(ext.into()))
+}
+
+/// Rejects `options` that `format` can't honour.
+pub fn check_options(format: ImageFormat, options: ExportOptions) -> Result<(), ExportError> {
+    if format == ImageFormat::WebP && options.quality.is_some() {
+        return Err(ExportError::LosslessWebp);
+    }
+    Ok(())
+}
+
+/// Encodes `image` into `path` in the format its extension names. The file is
+/// written under a temporary name first and moved into place, so overwriting
+/// an image never leaves a truncated file behind.
+pub fn save(image: &DynamicImage, path: &Path, options: ExportOptions) -> Result<()> {
+    let ext = path.extension().unwrap_or_default().to_string_lossy();
+    let format = format_for(&ext)?;
+    check_options(format, options)?;
+    let image = convert_for(image, format, &ext);
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    // keep the extension, the PNM encoder picks its subtype by it
+    let temp = path.with_file_name(format!(".part-{name}"));
+    if let Err(e) = encode(&image, &temp, format, options) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    fs::rename(&temp, path)?;
+    info!(
+        "Wrote {}x{} {format:?} image to {path:?}",
+        image.width(),
+        image.height()
+    );
+    Ok(())
+}
+
+/// Converts `image` to pixels `format` can hold. Alpha and high bit depths
+/// are kept where the format has them, icons are shrunk to fit.
+/// `ext` tells the PNM flavours apart.
+fn convert_for(image: &DynamicImage, format: ImageFormat, ext: &str) -> DynamicImage {
+    let alpha = image.color().has_alpha();
+    match format {
+        ImageFormat::Jpeg if image.color() == ColorType::L8 => image.clone(),
+        ImageFormat::Pnm if ext.eq_ignore_ascii_case("pgm") => {
+            DynamicImage::ImageLuma8(image.to_luma8())
+        }
+        ImageFormat::Pnm if ext.eq_ignore_ascii_case("pam") && alpha => {
+            DynamicImage::ImageRgba8(image.to_rgba8())
+        }
+        ImageFormat::Jpeg | ImageFormat::Pnm => DynamicImage::ImageRgb8(image.to_rgb8()),
+        ImageFormat::Hdr => DynamicImage::ImageRgb32F(image.to_rgb32f()),
+        ImageFormat::Ico if image.width() > ICO_MAX_SIZE || image.height() > ICO_MAX_SIZE => {
+            let icon = image.resize(ICO_MAX_SIZE, ICO_MAX_SIZE, FilterType::Lanczos3);
+            DynamicImage::ImageRgba8(icon.to_rgba8())
+        }
+        ImageFormat::OpenExr if alpha => DynamicImage::ImageRgba32F(image.to_rgba32f()),
+        ImageFormat::OpenExr => DynamicImage::ImageRgb32F(image.to_rgb32f()),
+        ImageFormat::Farbfeld => DynamicImage::ImageRgba16(image.to_rgba16()),
+        ImageFormat::Png | ImageFormat::Tiff if tonemap::is_float(image) => {
+            DynamicImage::ImageRgba16(image.to_rgba16())
+        }
+        ImageFormat::Png | ImageFormat::Tiff => image.clone(),
+        // everything else takes 8 bit RGB and most of it alpha
+        _ if alpha => DynamicImage::ImageRgba8(image.to_rgba8()),
+        _ => DynamicImage::ImageRgb8(image.to_rgb8()),
+    }
+}
+
+fn encode(
+    image: &DynamicImage,
+    path: &Path,
+    format: ImageFormat,
+    options: ExportOptions,
+) -> Result<()> {
+    let (w, h) = (image.width(), image.height());
+    match format {
+        ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            match format {
+                ImageFormat::Jpeg => JpegEncoder::new_with_quality(
+                    &mut writer,
+                    options.quality.unwrap_or(DEFAULT_QUALITY),
+                )
+                .write_image(image.as_bytes(), w, h, image.color())?,
+                ImageFormat::Png => PngEncoder::new_with_quality(
+                    &mut writer,
+                    options.png_compression.into(),
+                    png::FilterType::Adaptive,
+                )
+                .write_image(image.as_bytes(), w, h, image.color())?,
+                _ => {
+                    // lossless, `check_options` turned down a quality
+                    let color = match image.color() {
+                        ColorType::Rgba8 => image_webp::ColorType::Rgba8,
+                        _ => image_webp::ColorType::Rgb8,
+                    };
+                    image_webp::WebPEncoder::new(&mut writer).encode(
+                        image.as_bytes(),
+                        w,
+                        h,
+                        color,
+                    )?
+                }
+            }
+            writer.flush()?;
+        }
+        ImageFormat::Hdr => {
+            // `save_with_format` has no HDR encoder
+            let mut writer = BufWriter::new(File::create(path)?);
+            let pixels = image.to_rgb32f();
+            HdrEncoder::new(&mut writer).encode(
+                &pixels.pixels().copied().collect::<Vec<_>>(),
+                w as usize,
+                h as usize,
+            )?;
+            writer.flush()?;
+        }
+        _ => image.save_with_format(path, format)?,
+    }
+    Ok(())
+}
+
+/// Runs `mevi export`: converts every file to the format of `args`. Returns
+/// the exit code.
+pub fn run(args: &ExportArgs) -> i32 {
+    if let Err(e) = format_for(&args.format).and_then(|format| check_options(format, args.options))
+    {
+        err!("{e}");
+        return EXPORT_ERROR_EXIT_CODE;
+    }
+    if let Some(dir) = &args.output {
+        if let Err(e) = fs::create_dir_all(dir) {
+            err!("Failed to create {dir:?}: {e}");
+            return EXPORT_ERROR_EXIT_CODE;
+        }
+    }
+    let mut failed = false;
+    for path in &args.paths {
+        match export_file(path, args) {
+            Ok(target) => println!("{}", target.display()),
+            Err(e) => {
+                err!("Failed to export {path:?}: {e}");
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        EXPORT_ERROR_EXIT_CODE
+    } else {
+        0
+    }
+}
+
+/// Writes `path` as `<stem>.<format>` into the output directory, or next to
+/// `path` when there is none.
+fn export_file(path: &Path, args: &ExportArgs) -> Result<PathBuf> {
+    let stem = path
+        .file_stem()
+        .ok_or_else(|| anyhow!("Not a file: {path:?}"))?;
+    // the extension asked for, not the format's first one, so `-f ppm` doesn't
+    // end up as a .pbm
+    let ext = args.format.to_ascii_lowercase();
+    let name = format!("{}.{ext}", stem.to_string_lossy());
+    let target = match &args.output {
+        Some(dir) => dir.join(name),
+        None => path.with_file_name(name),
+    };
+    if target.exists() && !args.overwrite {
+        return Err(ExportError::Exists(target).into());
+    }
+
+    let mut image = img::decode_file(path)?;
+    if let Some(profile) = icc::extract(path) {
+        image = icc::to_srgb(image, &profile);
+    }
+    save(&image, &target, args.options)?;
+    Ok(target)
+}
@@ -0,0 +1,568 @@
+use std::{
+    env,
+    ffi::OsString,
+    fs::{self, DirBuilder, File, OpenOptions},
+    io::{self, ErrorKind, Write},
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::{DirBuilderExt, MetadataExt},
+    },
+    path::{Path, PathBuf},
+};
+
+use chrono::Local;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum FileOpError {
+    #[error("Could not determine the trash directory")]
+    NoTrashDir,
+    #[error("{0:?} already exists")]
+    Exists(PathBuf),
+    #[error("Invalid file name: {0:?}")]
+    InvalidName(String),
+    #[error("No destination directory configured")]
+    NoDestination,
+    #[error("{0:?} is not a directory")]
+    NotADirectory(PathBuf),
+    #[error("Malformed trash info file {0:?}")]
+    MalformedTrashInfo(PathBuf),
+    #[error("File operation failed: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// A file that was moved into a trash directory.
+#[derive(Debug, Clone)]
+pub struct TrashedFile {
+    pub original: PathBuf,
+    pub trashed: PathBuf,
+    pub info: PathBuf,
+}
+
+/// A reversible file operation performed during the session.
+#[derive(Debug, Clone)]
+pub enum FileOp {
+    Trash {
+        file: TrashedFile,
+        index: usize,
+        marked: bool,
+    },
+    Move {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+    },
+}
+
+impl FileOp {
+    /// Reverts the operation on disk.
+    pub fn undo(&self) -> Result<PathBuf, FileOpError> {
+        match self {
+            FileOp::Trash { file, .. } => restore(file),
+            FileOp::Move { from, to } | FileOp::Rename { from, to } => {
+                move_file(to, from)?;
+                info!("Moved {to:?} back to {from:?}");
+                Ok(from.clone())
+            }
+        }
+    }
+
+    pub fn describe_undo(&self) -> String {
+        match self {
+            FileOp::Trash { file, .. } => format!("Restored {}", file.original.display()),
+            FileOp::Move { from, .. } => format!("Moved back to {}", from.display()),
+            FileOp::Rename { from, to } => format!(
+                "Renamed {} back to {}",
+                to.file_name().unwrap_or_default().to_string_lossy(),
+                from.file_name().unwrap_or_default().to_string_lossy()
+            ),
+        }
+    }
+}
+
+/// Moves `path` to the trash as described by the freedesktop.org Trash
+/// specification. Files on the same device as the home trash go there,
+/// others go to `$topdir/.Trash/$uid` or `$topdir/.Trash-$uid` on their own
+/// mount.
+pub fn trash(path: &Path) -> Result<TrashedFile, FileOpError> {
+    // only the directory is resolved, a symlink is trashed itself rather
+    // than the file it points to
+    let name = path
+        .file_name()
+        .ok_or_else(|| FileOpError::InvalidName(path.to_string_lossy().into()))?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let path = dir.canonicalize()?.join(name);
+    let home_trash = home_trash_dir().ok_or(FileOpError::NoTrashDir)?;
+    ensure_trash_dir(&home_trash)?;
+
+    let file_dev = fs::symlink_metadata(&path)?.dev();
+    let (trash_dir, info_path) = if fs::metadata(&home_trash)?.dev() == file_dev {
+        (home_trash, path.clone())
+    } else {
+        let topdir = mount_point(&path)?;
+        let trash_dir = topdir_trash_dir(&topdir)?;
+        let relative = path.strip_prefix(&topdir).unwrap_or(&path).to_path_buf();
+        (trash_dir, relative)
+    };
+
+    let (name, info) = create_trash_info(&trash_dir, &path, &info_path)?;
+    let trashed = trash_dir.join("files").join(name);
+    // replaces the empty file that reserved the name
+    if let Err(e) = fs::rename(&path, &trashed) {
+        fs::remove_file(&trashed)?;
+        fs::remove_file(&info)?;
+        return Err(e.into());
+    }
+
+    Ok(TrashedFile {
+        original: path,
+        trashed,
+        info,
+    })
+}
+
+/// Restores a trashed file to the location recorded in its `.trashinfo`
+/// file and removes the info file.
+pub fn restore(file: &TrashedFile) -> Result<PathBuf, FileOpError> {
+    let malformed = || FileOpError::MalformedTrashInfo(file.info.clone());
+    let contents = fs::read_to_string(&file.info)?;
+    let encoded = contents
+        .lines()
+        .skip_while(|l| l.trim() != "[Trash Info]")
+        .find_map(|l| l.strip_prefix("Path="))
+        .ok_or_else(malformed)?;
+    let path = PathBuf::from(OsString::from_vec(
+        url_decode(encoded.trim()).ok_or_else(malformed)?,
+    ));
+
+    // relative paths are relative to the directory the trash lives in
+    let original = if path.is_absolute() {
+        path
+    } else {
+        let trash_dir = file
+            .info
+            .parent()
+            .and_then(Path::parent)
+            .ok_or_else(malformed)?;
+        let mut topdir = trash_dir.parent().ok_or_else(malformed)?;
+        // `$topdir/.Trash/$uid` is one level deeper than `$topdir/.Trash-$uid`
+        if topdir.file_name() == Some(".Trash".as_ref()) {
+            topdir = topdir.parent().ok_or_else(malformed)?;
+        }
+        topdir.join(path)
+    };
+
+    move_file(&file.trashed, &original)?;
+    fs::remove_file(&file.info)?;
+    info!("Restored {:?} to {original:?}", file.trashed);
+    Ok(original)
+}
+
+/// Renames `path` to `name` inside the same directory.
+pub fn rename(path: &Path, name: &str) -> Result<PathBuf, FileOpError> {
+    let target = sibling(path, name)?;
+    move_file(path, &target)?;
+    info!("Renamed {path:?} to {target:?}");
+    Ok(target)
+}
+
+/// The path of a file called `name` in the same directory as `path`.
+pub fn sibling(path: &Path, name: &str) -> Result<PathBuf, FileOpError> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(FileOpError::InvalidName(name.into()));
+    }
+    Ok(path.with_file_name(name))
+}
+
+/// Moves `path` into the directory `dest`, falling back to copy and remove
+/// when the destination is on another device.
+pub fn move_to(path: &Path, dest: &Path) -> Result<PathBuf, FileOpError> {
+    let (target, file) = claim_in(path, dest)?;
+    move_onto(path, &target, file)?;
+    info!("Moved {path:?} to {target:?}");
+    Ok(target)
+}
+
+/// Copies `path` into the directory `dest`.
+pub fn copy_to(path: &Path, dest: &Path) -> Result<PathBuf, FileOpError> {
+    let (target, file) = claim_in(path, dest)?;
+    if let Err(e) = copy_into(path, file) {
+        fs::remove_file(&target)?;
+        return Err(e.into());
+    }
+    info!("Copied {path:?} to {target:?}");
+    Ok(target)
+}
+
+/// Moves `from` to `to`, refusing to replace anything already there.
+pub fn move_file(from: &Path, to: &Path) -> Result<(), FileOpError> {
+    let file = claim(to)?;
+    move_onto(from, to, file)
+}
+
+/// Moves `from` over the empty file `to` that was claimed for it.
+fn move_onto(from: &Path, to: &Path, file: File) -> Result<(), FileOpError> {
+    let moved = match fs::rename(from, to) {
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+            copy_into(from, file).and_then(|_| fs::remove_file(from))
+        }
+        res => res,
+    };
+    if let Err(e) = moved {
+        fs::remove_file(to)?;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+fn copy_into(from: &Path, mut to: File) -> io::Result<()> {
+    let mut source = File::open(from)?;
+    io::copy(&mut source, &mut to)?;
+    to.set_permissions(source.metadata()?.permissions())
+}
+
+/// Creates an empty file at `path` to reserve the name.
+fn claim(path: &Path) -> Result<File, FileOpError> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| match e.kind() {
+            ErrorKind::AlreadyExists => FileOpError::Exists(path.to_path_buf()),
+            _ => e.into(),
+        })
+}
+
+/// Reserves the name of `path` in the directory `dest`, trying `name-2.ext`,
+/// `name-3.ext` and so on when it's taken.
+fn claim_in(path: &Path, dest: &Path) -> Result<(PathBuf, File), FileOpError> {
+    if !dest.is_dir() {
+        return Err(FileOpError::NotADirectory(dest.to_path_buf()));
+    }
+    let stem = path
+        .file_stem()
+        .ok_or_else(|| FileOpError::InvalidName(path.to_string_lossy().into()))?;
+    for n in 1.. {
+        let mut name = stem.to_os_string();
+        if n > 1 {
+            name.push(format!("-{n}"));
+        }
+        if let Some(ext) = path.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        let target = dest.join(name);
+        match claim(&target) {
+            Ok(file) => return Ok((target, file)),
+            Err(FileOpError::Exists(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!()
+}
+
+fn home_trash_dir() -> Option<PathBuf> {
+    let data = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/share")))?;
+    Some(data.join("Trash"))
+}
+
+/// The trash directory for files on the mount at `topdir`. The shared
+/// `$topdir/.Trash` is only used when it's a real directory with the sticky
+/// bit set, as the specification requires.
+fn topdir_trash_dir(topdir: &Path) -> io::Result<PathBuf> {
+    let uid = unsafe { libc::getuid() };
+    let shared = topdir.join(".Trash");
+    if let Ok(meta) = fs::symlink_metadata(&shared) {
+        if meta.is_dir() && meta.mode() & libc::S_ISVTX != 0 {
+            let dir = shared.join(uid.to_string());
+            match ensure_trash_dir(&dir) {
+                Ok(()) => return Ok(dir),
+                Err(e) => info!("Can't use {dir:?}: {e}"),
+            }
+        } else {
+            info!("Ignoring {shared:?}, it's not a sticky directory");
+        }
+    }
+    let dir = topdir.join(format!(".Trash-{uid}"));
+    ensure_trash_dir(&dir)?;
+    Ok(dir)
+}
+
+fn ensure_trash_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = DirBuilder::new();
+    builder.recursive(true).mode(0o700);
+    builder.create(dir.join("files"))?;
+    builder.create(dir.join("info"))
+}
+
+/// Walks up from `path` until the parent is on a different device.
+fn mount_point(path: &Path) -> io::Result<PathBuf> {
+    let dev = fs::symlink_metadata(path)?.dev();
+    let mut topdir = path;
+    while let Some(parent) = topdir.parent() {
+        if fs::metadata(parent)?.dev() != dev {
+            break;
+        }
+        topdir = parent;
+    }
+    Ok(topdir.to_path_buf())
+}
+
+/// Atomically reserves a name in `trash_dir` by creating its `.trashinfo`
+/// file and an empty file under `files`. Returns the reserved name and the
+/// info file path.
+fn create_trash_info(
+    trash_dir: &Path,
+    path: &Path,
+    info_path: &Path,
+) -> Result<(OsString, PathBuf), FileOpError> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| FileOpError::InvalidName(path.to_string_lossy().into()))?;
+    let contents = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        url_encode(info_path.as_os_str().as_bytes()),
+        Local::now().format("%Y-%m-%dT%H:%M:%S")
+    );
+
+    for n in 1.. {
+        let mut name = file_name.to_os_string();
+        if n > 1 {
+            name.push(format!(".{n}"));
+        }
+        let mut info_name = name.clone();
+        info_name.push(".trashinfo");
+        let info = trash_dir.join("info").join(info_name);
+
+        let mut f = match claim(&info) {
+            Ok(f) => f,
+            Err(FileOpError::Exists(_)) => continue,
+            Err(e) => return Err(e),
+        };
+        match claim(&trash_dir.join("files").join(&name)) {
+            Ok(_) => {
+                f.write_all(contents.as_bytes())?;
+                return Ok((name, info));
+            }
+            Err(FileOpError::Exists(_)) => fs::remove_file(&info)?,
+            Err(e) => {
+                fs::remove_file(&info)?;
+                return Err(e);
+            }
+        }
+    }
+    unreachable!()
+}
+
+fn url_decode(s: &str) -> Option<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(out)
+}
+
+fn url_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    for &b in bytes {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{os::unix::fs::symlink, sync::OnceLock};
+    use tempfile::TempDir;
+
+    /// Points the home trash at a directory of its own, shared by every
+    /// test since the environment is.
+    fn use_test_trash() -> PathBuf {
+        static DATA_HOME: OnceLock<TempDir> = OnceLock::new();
+        let data = DATA_HOME.get_or_init(|| {
+            let dir = TempDir::new().unwrap();
+            env::set_var("XDG_DATA_HOME", dir.path());
+            dir
+        });
+        data.path().join("Trash")
+    }
+
+    fn dir_with(files: &[(&str, &str)]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for (name, contents) in files {
+            fs::write(dir.path().join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn url_encoding_round_trips() {
+        let path = "/tmp/a b/ü%.png".as_bytes();
+        let encoded = url_encode(path);
+        assert_eq!(encoded, "/tmp/a%20b/%C3%BC%25.png");
+        assert_eq!(url_decode(&encoded).unwrap(), path);
+        assert_eq!(url_decode("%2"), None);
+        assert_eq!(url_decode("%zz"), None);
+    }
+
+    #[test]
+    fn move_file_never_overwrites() {
+        let dir = dir_with(&[("a.png", "a"), ("b.png", "b")]);
+        let (a, b) = (dir.path().join("a.png"), dir.path().join("b.png"));
+        assert!(matches!(move_file(&a, &b), Err(FileOpError::Exists(_))));
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "b");
+
+        let c = dir.path().join("c.png");
+        move_file(&a, &c).unwrap();
+        assert!(!a.exists());
+        assert_eq!(fs::read_to_string(&c).unwrap(), "a");
+    }
+
+    #[test]
+    fn copy_and_move_pick_free_names() {
+        let src = dir_with(&[("x.png", "new"), ("y", "y")]);
+        let dest = dir_with(&[("x.png", "old"), ("x-2.png", "old")]);
+        let copied = copy_to(&src.path().join("x.png"), dest.path()).unwrap();
+        assert_eq!(copied, dest.path().join("x-3.png"));
+        assert_eq!(fs::read_to_string(&copied).unwrap(), "new");
+        assert_eq!(
+            fs::read_to_string(dest.path().join("x.png")).unwrap(),
+            "old"
+        );
+
+        let moved = move_to(&src.path().join("y"), dest.path()).unwrap();
+        assert_eq!(moved, dest.path().join("y"));
+        assert!(!src.path().join("y").exists());
+
+        let file = src.path().join("x.png");
+        assert!(matches!(
+            copy_to(&file, &file),
+            Err(FileOpError::NotADirectory(_))
+        ));
+    }
+
+    #[test]
+    fn rename_rejects_paths() {
+        let dir = dir_with(&[("a.png", "a")]);
+        let a = dir.path().join("a.png");
+        for name in ["", ".", "..", "sub/b.png"] {
+            assert!(matches!(rename(&a, name), Err(FileOpError::InvalidName(_))));
+        }
+        assert_eq!(rename(&a, "b.png").unwrap(), dir.path().join("b.png"));
+    }
+
+    #[test]
+    fn trash_writes_info_and_restores() {
+        let trash_dir = use_test_trash();
+        let dir = dir_with(&[("a b.png", "a")]);
+        let path = dir.path().canonicalize().unwrap().join("a b.png");
+
+        let trashed = trash(&path).unwrap();
+        assert!(!path.exists());
+        assert_eq!(trashed.original, path);
+        assert!(trashed.trashed.starts_with(trash_dir.join("files")));
+        assert_eq!(fs::read_to_string(&trashed.trashed).unwrap(), "a");
+
+        let info = fs::read_to_string(&trashed.info).unwrap();
+        let mut lines = info.lines();
+        assert_eq!(lines.next(), Some("[Trash Info]"));
+        let encoded = url_encode(path.as_os_str().as_bytes());
+        assert_eq!(lines.next(), Some(format!("Path={encoded}").as_str()));
+        assert!(lines.next().unwrap().starts_with("DeletionDate="));
+
+        assert_eq!(restore(&trashed).unwrap(), path);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a");
+        assert!(!trashed.info.exists());
+        assert!(!trashed.trashed.exists());
+    }
+
+    #[test]
+    fn trash_keeps_files_with_the_same_name_apart() {
+        use_test_trash();
+        let (first, second) = (
+            dir_with(&[("same.png", "1")]),
+            dir_with(&[("same.png", "2")]),
+        );
+        let a = trash(&first.path().join("same.png")).unwrap();
+        let b = trash(&second.path().join("same.png")).unwrap();
+        assert_ne!(a.trashed, b.trashed);
+        assert_eq!(fs::read_to_string(&a.trashed).unwrap(), "1");
+        assert_eq!(fs::read_to_string(&b.trashed).unwrap(), "2");
+        restore(&a).unwrap();
+        restore(&b).unwrap();
+    }
+
+    #[test]
+    fn trash_moves_symlinks_not_their_targets() {
+        use_test_trash();
+        let dir = dir_with(&[("target.png", "t")]);
+        let target = dir.path().join("target.png");
+        let link = dir.path().join("link.png");
+        symlink(&target, &link).unwrap();
+
+        let trashed = trash(&link).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "t");
+        assert!(fs::symlink_metadata(&link).is_err());
+        assert!(fs::symlink_metadata(&trashed.trashed)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(trashed.original.file_name(), link.file_name());
+
+        restore(&trashed).unwrap();
+        assert_eq!(fs::read_link(&link).unwrap(), target);
+    }
+
+    #[test]
+    fn restore_never_overwrites() {
+        use_test_trash();
+        let dir = dir_with(&[("a.png", "old")]);
+        let path = dir.path().join("a.png");
+        let trashed = trash(&path).unwrap();
+        fs::write(&path, "new").unwrap();
+
+        assert!(matches!(restore(&trashed), Err(FileOpError::Exists(_))));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_to_string(&trashed.trashed).unwrap(), "old");
+        assert!(trashed.info.exists());
+    }
+
+    #[test]
+    fn restore_rejects_malformed_info() {
+        let dir = dir_with(&[("x.trashinfo", "[Trash Info]\nDeletionDate=now\n")]);
+        let file = TrashedFile {
+            original: dir.path().join("x"),
+            trashed: dir.path().join("files/x"),
+            info: dir.path().join("x.trashinfo"),
+        };
+        assert!(matches!(
+            restore(&file),
+            Err(FileOpError::MalformedTrashInfo(_))
+        ));
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use image::DynamicImage;
+
+use crate::{
+    archive::Archive,
+    font::{FontDrawer, RenderLine, ToRenderLine},
+};
+
+/// An image inside an archive.
+#[derive(Debug, Clone)]
+pub struct ArchiveMember {
+    pub archive: Rc<Archive>,
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileEntry {
+    /// The file on disk, or for archive members the archive path joined with
+    /// the member name.
+    pub path: PathBuf,
+    pub marked: bool,
+    pub member: Option<ArchiveMember>,
+    /// Pixels grabbed from the screen, for entries that aren't saved yet.
+    pub capture: Option<Rc<DynamicImage>>,
+}
+
+impl FileEntry {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            marked: false,
+            member: None,
+            capture: None,
+        }
+    }
+
+    /// Whether `path` names a real file, which archive members and unsaved
+    /// captures don't.
+    pub fn on_disk(&self) -> bool {
+        self.member.is_none() && self.capture.is_none()
+    }
+}
+
+#[derive(Debug)]
+pub struct FileList {
+    entries: Vec<FileEntry>,
+    current: usize,
+}
+
+impl FileList {
+    /// Lists `paths`, replacing archives by the images inside them.
+    pub fn new(paths: &[PathBuf]) -> Self {
+        let mut entries = vec![];
+        for path in paths {
+            let Some(kind) = Archive::detect(path) else {
+                entries.push(FileEntry::new(path.clone()));
+                continue;
+            };
+            match Archive::open(path, kind) {
+                Ok(archive) => {
+                    let archive = Rc::new(archive);
+                    entries.extend(archive.members.iter().map(|name| FileEntry {
+                        path: path.join(name),
+                        marked: false,
+                        member: Some(ArchiveMember {
+                            archive: archive.clone(),
+                            name: name.clone(),
+                        }),
+                        capture: None,
+                    }));
+                }
+                Err(e) => {
+                    err!("Failed to open archive {path:?}: {e}");
+                    entries.push(FileEntry::new(path.clone()));
+                }
+            }
+        }
+        Self {
+            entries,
+            current: 0,
+        }
+    }
+
+    /// A list of just the screen capture `image`, to be saved as `path`.
+    pub fn captured(path: PathBuf, image: DynamicImage) -> Self {
+        let mut entry = FileEntry::new(path);
+        entry.capture = Some(Rc::new(image));
+        Self {
+            entries: vec![entry],
+            current: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn index(&self) -> usize {
+        self.current
+    }
+
+    pub fn current(&self) -> &Path {
+        &self.entries[self.current].path
+    }
+
+    pub fn current_entry(&self) -> &FileEntry {
+        &self.entries[self.current]
+    }
+
+    pub fn entry(&self, i: usize) -> Option<&FileEntry> {
+        self.entries.get(i)
+    }
+
+    pub fn is_marked(&self) -> bool {
+        self.entries.get(self.current).is_some_and(|e| e.marked)
+    }
+
+    pub fn position(&self, path: &Path) -> Option<usize> {
+        self.entries.iter().position(|e| e.path == path)
+    }
+
+    pub fn set_path(&mut self, i: usize, path: PathBuf) {
+        self.entries[i].path = path;
+    }
+
+    /// Inserts a new entry at `i` and makes it the current one.
+    pub fn insert(&mut self, i: usize, path: PathBuf, marked: bool) {
+        let i = i.min(self.entries.len());
+        let mut entry = FileEntry::new(path);
+        entry.marked = marked;
+        self.entries.insert(i, entry);
+        self.current = i;
+    }
+
+    pub fn set_current_path(&mut self, path: PathBuf) {
+        self.entries[self.current].path = path;
+    }
+
+    pub fn set_index(&mut self, i: usize) {
+        self.current = i.min(self.entries.len().saturating_sub(1));
+    }
+
+    pub fn next_index(&self) -> usize {
+        (self.current + 1) % self.entries.len()
+    }
+
+    pub fn prev_index(&self) -> usize {
+        if self.current == 0 {
+            self.entries.len() - 1
+        } else {
+            self.current - 1
+        }
+    }
+
+    /// Removes the entry at `i`, keeping the current index pointing at the
+    /// same file where possible. Returns the removed entry.
+    pub fn remove(&mut self, i: usize) -> Option<FileEntry> {
+        if i >= self.entries.len() {
+            return None;
+        }
+        let entry = self.entries.remove(i);
+        if i < self.current || self.current >= self.entries.len() {
+            self.current = self.current.saturating_sub(1);
+        }
+        Some(entry)
+    }
+
+    pub fn toggle_mark(&mut self) {
+        let entry = &mut self.entries[self.current];
+        entry.marked = !entry.marked;
+        info!("Marked {:?}: {}", entry.path, entry.marked);
+    }
+
+    pub fn mark_all(&mut self) {
+        self.entries.iter_mut().for_each(|e| e.marked = true);
+        info!("Marked all {} files", self.entries.len());
+    }
+
+    pub fn unmark_all(&mut self) {
+        self.entries.iter_mut().for_each(|e| e.marked = false);
+        info!("Unmarked all files");
+    }
+
+    pub fn invert_marks(&mut self) {
+        self.entries.iter_mut().for_each(|e| e.marked = !e.marked);
+        info!("Inverted marks");
+    }
+
+    pub fn marked_count(&self) -> usize {
+        self.entries.iter().filter(|e| e.marked).count()
+    }
+
+    /// Paths of the marked files on disk. Marked archive members are left
+    /// out since their paths don't exist.
+    pub fn marked(&self) -> Vec<PathBuf> {
+        self.entries
+            .iter()
+            .filter(|e| e.marked && e.on_disk())
+            .map(|e| e.path.clone())
+            .collect()
+    }
+
+    /// The marked files, or the current file if nothing is marked, leaving
+    /// out archive members.
+    pub fn selection(&self) -> Vec<PathBuf> {
+        if self.marked_count() > 0 {
+            self.marked()
+        } else if self.current_entry().on_disk() {
+            vec![self.current().to_path_buf()]
+        } else {
+            vec![]
+        }
+    }
+}
+
+impl ToRenderLine for FileList {
+    fn to_lines(&self, font_drawer: &FontDrawer) -> Vec<RenderLine> {
+        let mark = if self.is_marked() { " (marked)" } else { "" };
+        vec![
+            RenderLine::new(
+                font_drawer,
+                format!(
+                    "file: {}/{}{mark}",
+                    (self.current + 1).min(self.len()),
+                    self.len()
+                ),
+            ),
+            RenderLine::new(font_drawer, format!("marked: {}", self.marked_count())),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A list of files that don't exist, which isn't checked.
+    fn list(names: &[&str]) -> FileList {
+        FileList::new(&names.iter().map(PathBuf::from).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn remove_keeps_the_current_file() {
+        let mut files = list(&["a", "b", "c", "d"]);
+        files.set_index(2);
+        files.remove(0);
+        assert_eq!(files.current(), Path::new("c"));
+        files.remove(2);
+        assert_eq!(files.current(), Path::new("c"));
+        assert!(files.remove(5).is_none());
+        assert_eq!(files.len(), 2);
+    }
+
+    #[test]
+    fn remove_moves_back_from_the_end() {
+        let mut files = list(&["a", "b", "c"]);
+        files.set_index(2);
+        files.remove(2);
+        assert_eq!(files.current(), Path::new("b"));
+        files.remove(1);
+        files.remove(0);
+        assert_eq!(files.len(), 0);
+        assert_eq!(files.index(), 0);
+        assert!(!files.is_marked());
+    }
+
+    #[test]
+    fn insert_brings_back_the_mark() {
+        let mut files = list(&["a", "c"]);
+        files.insert(1, "b".into(), true);
+        assert_eq!(files.current(), Path::new("b"));
+        assert!(files.is_marked());
+        files.insert(10, "d".into(), false);
+        assert_eq!(files.index(), 3);
+        assert_eq!(files.marked(), vec![PathBuf::from("b")]);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut files = list(&["a", "b", "c"]);
+        assert_eq!(files.prev_index(), 2);
+        files.set_index(7);
+        assert_eq!(files.index(), 2);
+        assert_eq!(files.next_index(), 0);
+    }
+
+    #[test]
+    fn selection_leaves_out_captures() {
+        let mut files = list(&["a", "b"]);
+        assert_eq!(files.selection(), vec![PathBuf::from("a")]);
+        files.set_index(1);
+        files.toggle_mark();
+        files.set_index(0);
+        assert_eq!(files.selection(), vec![PathBuf::from("b")]);
+
+        let mut captured = FileList::captured("capture.png".into(), DynamicImage::new_rgb8(1, 1));
+        assert!(captured.selection().is_empty());
+        captured.mark_all();
+        assert!(captured.marked().is_empty());
+    }
+}
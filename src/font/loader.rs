@@ -16,6 +16,7 @@ pub enum FontError {
     #[error("Failed to create glyphset: {0:?}")]
     CreateGlyphset(#[from] ConnectionError),
     #[error("Failed to create glyphset ID: {0:?}")]
+    #[allow(clippy::upper_case_acronyms)]
     GSID(#[from] ReplyOrIdError),
 }
 
@@ -23,6 +24,7 @@ pub struct LoadedFont {
     pub gsid: Glyphset,
     pub char_map: Map<char, CharInfo>,
     pub font_height: i16,
+    #[allow(dead_code)]
     pub font: Font,
 }
 
@@ -35,6 +37,7 @@ pub struct CharInfo {
 #[derive(Debug, Clone)]
 pub struct FontEncodedChunk {
     pub width: i16,
+    #[allow(dead_code)]
     pub font_height: i16,
     pub glyph_set: Glyphset,
     pub glyph_ids: Vec<u32>,
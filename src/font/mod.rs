@@ -22,6 +22,7 @@ impl FontDrawer {
         Self { font }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn draw<C: Connection>(
         &self,
         conn: &C,
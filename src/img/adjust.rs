@@ -0,0 +1,151 @@
+//! Display adjustments applied to the rendered copy of the image. The file and
+//! the decoded pixels are never touched.
+
+use image::{ImageBuffer, Pixel, Primitive, Rgb};
+
+pub static BRIGHTNESS_STEP: f32 = 0.05;
+pub static CONTRAST_STEP: f32 = 0.1;
+pub static GAMMA_STEP: f32 = 0.1;
+pub static SATURATION_STEP: f32 = 0.1;
+
+/// A change to the adjustments, bound to a key.
+#[derive(Debug, Clone, Copy)]
+pub enum Adjustment {
+    Brightness(f32),
+    Contrast(f32),
+    Gamma(f32),
+    Saturation(f32),
+    Invert,
+    Grayscale,
+    Reset,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adjustments {
+    /// Added to every channel, in -1..=1.
+    pub brightness: f32,
+    /// Slope around mid grey minus one, in -1..=1.
+    pub contrast: f32,
+    pub gamma: f32,
+    /// Distance from grey, 1 leaves colours as they are.
+    pub saturation: f32,
+    pub invert: bool,
+    pub grayscale: bool,
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Self {
+            brightness: 0.0,
+            contrast: 0.0,
+            gamma: 1.0,
+            saturation: 1.0,
+            invert: false,
+            grayscale: false,
+        }
+    }
+}
+
+impl Adjustments {
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn change(&mut self, change: Adjustment) {
+        match change {
+            Adjustment::Brightness(d) => self.brightness = (self.brightness + d).clamp(-1.0, 1.0),
+            Adjustment::Contrast(d) => self.contrast = (self.contrast + d).clamp(-1.0, 1.0),
+            Adjustment::Gamma(d) => self.gamma = (self.gamma + d).clamp(0.1, 5.0),
+            Adjustment::Saturation(d) => self.saturation = (self.saturation + d).clamp(0.0, 3.0),
+            Adjustment::Invert => self.invert = !self.invert,
+            Adjustment::Grayscale => self.grayscale = !self.grayscale,
+            Adjustment::Reset => *self = Self::default(),
+        }
+        // keep the steps from drifting away from round values
+        for value in [
+            &mut self.brightness,
+            &mut self.contrast,
+            &mut self.gamma,
+            &mut self.saturation,
+        ] {
+            *value = (*value * 100.0).round() / 100.0;
+        }
+    }
+
+    /// The adjustments that differ from the defaults, for the overlay.
+    pub fn describe(&self) -> String {
+        let mut parts = vec![];
+        if self.brightness != 0.0 {
+            parts.push(format!("brightness {:+.2}", self.brightness));
+        }
+        if self.contrast != 0.0 {
+            parts.push(format!("contrast {:+.1}", self.contrast));
+        }
+        if self.gamma != 1.0 {
+            parts.push(format!("gamma {:.1}", self.gamma));
+        }
+        if self.saturation != 1.0 && !self.grayscale {
+            parts.push(format!("saturation {:.1}", self.saturation));
+        }
+        if self.grayscale {
+            parts.push("grayscale".into());
+        }
+        if self.invert {
+            parts.push("inverted".into());
+        }
+        if parts.is_empty() {
+            "none".into()
+        } else {
+            parts.join(", ")
+        }
+    }
+
+    /// Adjusts an 8 or 10 bit RGB buffer in place. Saturation is changed
+    /// first, then brightness, contrast, gamma and inversion are applied to
+    /// each channel through a lookup table.
+    pub fn apply<S>(&self, buffer: &mut ImageBuffer<Rgb<S>, Vec<S>>, max: u16)
+    where
+        Rgb<S>: Pixel<Subpixel = S>,
+        S: Primitive + Into<u16> + TryFrom<u16>,
+    {
+        if self.is_identity() {
+            return;
+        }
+
+        let maxf = max as f32;
+        let lut = (0..=max)
+            .map(|v| {
+                let v = self.map_channel(v as f32 / maxf);
+                S::try_from((v * maxf).round() as u16).unwrap_or(S::DEFAULT_MAX_VALUE)
+            })
+            .collect::<Vec<_>>();
+        let saturation = if self.grayscale { 0.0 } else { self.saturation };
+
+        for pixel in buffer.pixels_mut() {
+            let [r, g, b] = pixel.0.map(|c| c.into());
+            let (r, g, b) = if saturation == 1.0 {
+                (r, g, b)
+            } else {
+                let luma = 0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32;
+                let saturate = |c: u16| {
+                    (luma + (c as f32 - luma) * saturation)
+                        .round()
+                        .clamp(0.0, maxf) as u16
+                };
+                (saturate(r), saturate(g), saturate(b))
+            };
+            pixel.0 = [lut[r as usize], lut[g as usize], lut[b as usize]];
+        }
+    }
+
+    fn map_channel(&self, v: f32) -> f32 {
+        let v = v + self.brightness;
+        let v = (v - 0.5) * (1.0 + self.contrast) + 0.5;
+        let v = v.clamp(0.0, 1.0).powf(1.0 / self.gamma);
+        if self.invert {
+            1.0 - v
+        } else {
+            v
+        }
+    }
+}
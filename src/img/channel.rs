@@ -0,0 +1,73 @@
+//! Views of a single channel of the image, shown in grey. The alpha channel
+//! shown this way is the image's transparency mask.
+
+use image::{DynamicImage, GrayImage, ImageBuffer, Luma, Rgb};
+
+use super::tonemap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    All,
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+impl Channel {
+    pub fn next(self) -> Self {
+        match self {
+            Channel::All => Channel::Red,
+            Channel::Red => Channel::Green,
+            Channel::Green => Channel::Blue,
+            Channel::Blue => Channel::Alpha,
+            Channel::Alpha => Channel::All,
+        }
+    }
+
+    fn index(self) -> Option<usize> {
+        match self {
+            Channel::All => None,
+            Channel::Red => Some(0),
+            Channel::Green => Some(1),
+            Channel::Blue => Some(2),
+            Channel::Alpha => Some(3),
+        }
+    }
+
+    /// The channel of `image` as a grey image of the same bit depth, or
+    /// `None` when showing all channels. Images without alpha have an opaque,
+    /// white mask.
+    pub fn isolate(self, image: &DynamicImage) -> Option<DynamicImage> {
+        let c = self.index()?;
+        let (w, h) = (image.width(), image.height());
+        let isolated = if tonemap::is_float(image) {
+            let rgba = image.to_rgba32f();
+            let buffer = ImageBuffer::from_fn(w, h, |x, y| Rgb([rgba.get_pixel(x, y).0[c]; 3]));
+            DynamicImage::ImageRgb32F(buffer)
+        } else if tonemap::bit_depth(image) > 8 {
+            let rgba = image.to_rgba16();
+            let buffer = ImageBuffer::from_fn(w, h, |x, y| Luma([rgba.get_pixel(x, y).0[c]]));
+            DynamicImage::ImageLuma16(buffer)
+        } else {
+            let rgba = image.to_rgba8();
+            DynamicImage::ImageLuma8(GrayImage::from_fn(w, h, |x, y| {
+                Luma([rgba.get_pixel(x, y).0[c]])
+            }))
+        };
+        Some(isolated)
+    }
+}
+
+impl std::fmt::Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Channel::All => "all",
+            Channel::Red => "red",
+            Channel::Green => "green",
+            Channel::Blue => "blue",
+            Channel::Alpha => "alpha mask",
+        };
+        write!(f, "{name}")
+    }
+}
@@ -0,0 +1,192 @@
+//! Decoders for formats the `image` crate can't open. Each one is behind a
+//! cargo feature since they rely on system libraries or tools:
+//!
+//! - `heif`: HEIF/HEIC through libheif
+//! - `avif`: AVIF through libheif, which needs to be built with an AV1 decoder
+//! - `jxl`: JPEG XL through libjxl's `djxl` tool, which has to be installed
+//! - `raw`: camera RAW files, see `img::raw`
+
+use std::{fs::File, io::Read, path::Path};
+
+use anyhow::Result;
+use image::DynamicImage;
+use thiserror::Error;
+
+/// Extensions of the TIFF based RAW formats.
+pub static RAW_EXTENSIONS: &[&str] = &["cr2", "nef", "nrw", "arw", "srf", "sr2", "dng", "pef"];
+
+#[derive(Error, Debug)]
+pub enum DecodeError {
+    #[cfg(not(all(feature = "heif", feature = "avif", feature = "jxl", feature = "raw")))]
+    #[error("{0} support is not enabled, rebuild with `--features {1}`")]
+    NotEnabled(ExtraFormat, &'static str),
+    #[cfg(feature = "heif")]
+    #[error("Failed to decode HEIF image: {0}")]
+    Heif(#[from] libheif_rs::HeifError),
+    #[cfg(feature = "heif")]
+    #[error("libheif returned no interleaved RGB data")]
+    NoPixelData,
+    #[cfg(feature = "jxl")]
+    #[error("JPEG XL decoding needs `djxl` from the libjxl tools on the PATH")]
+    NoDjxl,
+    #[cfg(feature = "jxl")]
+    #[error("djxl failed with {0}: {1}")]
+    Djxl(std::process::ExitStatus, String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtraFormat {
+    Heif,
+    Avif,
+    Jxl,
+    Raw,
+}
+
+impl std::fmt::Display for ExtraFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ExtraFormat::Heif => "HEIF",
+            ExtraFormat::Avif => "AVIF",
+            ExtraFormat::Jxl => "JPEG XL",
+            ExtraFormat::Raw => "camera RAW",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Sniffs the first bytes of `path` for one of the extra formats. RAW files
+/// look like TIFFs, so those go by their extension instead.
+pub fn detect(path: &Path) -> Option<ExtraFormat> {
+    let is_raw = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| RAW_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+        .unwrap_or(false);
+    if is_raw {
+        return Some(ExtraFormat::Raw);
+    }
+
+    let mut head = [0; 32];
+    let n = File::open(path).and_then(|mut f| f.read(&mut head)).ok()?;
+    let head = &head[..n];
+
+    if head.starts_with(&[0xff, 0x0a])
+        || head.starts_with(&[
+            0, 0, 0, 0x0c, b'J', b'X', b'L', b' ', 0x0d, 0x0a, 0x87, 0x0a,
+        ])
+    {
+        return Some(ExtraFormat::Jxl);
+    }
+
+    // ISO base media files start with an `ftyp` box holding the major brand
+    if head.len() >= 12 && &head[4..8] == b"ftyp" {
+        return match &head[8..12] {
+            b"avif" | b"avis" => Some(ExtraFormat::Avif),
+            b"heic" | b"heix" | b"heim" | b"heis" | b"hevc" | b"hevx" | b"hevm" | b"hevs"
+            | b"mif1" | b"msf1" => Some(ExtraFormat::Heif),
+            _ => None,
+        };
+    }
+    None
+}
+
+/// Decodes `path` as `format`, returning the image along with any extra
+/// lines for the file info.
+pub fn decode(path: &Path, format: ExtraFormat) -> Result<(DynamicImage, Vec<String>)> {
+    let (image, details) = match format {
+        #[cfg(feature = "heif")]
+        ExtraFormat::Heif => (heif::decode(path)?, vec![]),
+        #[cfg(not(feature = "heif"))]
+        ExtraFormat::Heif => Err(DecodeError::NotEnabled(format, "heif"))?,
+        #[cfg(feature = "avif")]
+        ExtraFormat::Avif => (heif::decode(path)?, vec![]),
+        #[cfg(not(feature = "avif"))]
+        ExtraFormat::Avif => Err(DecodeError::NotEnabled(format, "avif"))?,
+        #[cfg(feature = "jxl")]
+        ExtraFormat::Jxl => (jxl::decode(path)?, vec![]),
+        #[cfg(not(feature = "jxl"))]
+        ExtraFormat::Jxl => Err(DecodeError::NotEnabled(format, "jxl"))?,
+        #[cfg(feature = "raw")]
+        ExtraFormat::Raw => super::raw::decode(path)?,
+        #[cfg(not(feature = "raw"))]
+        ExtraFormat::Raw => Err(DecodeError::NotEnabled(format, "raw"))?,
+    };
+    info!("Decoded {format} image {path:?}");
+    Ok((image, details))
+}
+
+#[cfg(feature = "heif")]
+mod heif {
+    use super::DecodeError;
+    use image::{DynamicImage, RgbImage, RgbaImage};
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+    use std::path::Path;
+
+    pub fn decode(path: &Path) -> Result<DynamicImage, DecodeError> {
+        let lib = LibHeif::new();
+        let ctx = HeifContext::read_from_file(&path.to_string_lossy())?;
+        let handle = ctx.primary_image_handle()?;
+        let has_alpha = handle.has_alpha_channel();
+        let chroma = if has_alpha {
+            RgbChroma::Rgba
+        } else {
+            RgbChroma::Rgb
+        };
+        let image = lib.decode(&handle, ColorSpace::Rgb(chroma), None)?;
+
+        let plane = image.planes().interleaved.ok_or(DecodeError::NoPixelData)?;
+        let channels = if has_alpha { 4 } else { 3 };
+        let row_len = plane.width as usize * channels;
+
+        // rows may be padded, copy them out without the stride
+        let mut data = Vec::with_capacity(row_len * plane.height as usize);
+        for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+            data.extend_from_slice(&row[..row_len]);
+        }
+
+        let image = if has_alpha {
+            RgbaImage::from_raw(plane.width, plane.height, data).map(DynamicImage::ImageRgba8)
+        } else {
+            RgbImage::from_raw(plane.width, plane.height, data).map(DynamicImage::ImageRgb8)
+        };
+        image.ok_or(DecodeError::NoPixelData)
+    }
+}
+
+#[cfg(feature = "jxl")]
+mod jxl {
+    use super::DecodeError;
+    use anyhow::Result;
+    use image::DynamicImage;
+    use std::{
+        io::ErrorKind,
+        path::Path,
+        process::{Command, Stdio},
+    };
+
+    /// Decodes through a PNG written by `djxl`, which has to be on the
+    /// `PATH`. The PNG goes into a private temporary directory so nobody
+    /// else can put a file or symlink in its place.
+    pub fn decode(path: &Path) -> Result<DynamicImage> {
+        let dir = tempfile::Builder::new().prefix("mevi-").tempdir()?;
+        let out = dir.path().join("decoded.png");
+        let output = match Command::new("djxl")
+            .arg(path)
+            .arg(&out)
+            .stdout(Stdio::null())
+            .output()
+        {
+            Ok(output) => output,
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(DecodeError::NoDjxl)?,
+            Err(e) => Err(e)?,
+        };
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stderr.lines().last().unwrap_or_default().trim().to_string();
+            Err(DecodeError::Djxl(output.status, reason))?;
+        }
+        let image = image::open(&out)?;
+        dir.close()?;
+        Ok(image)
+    }
+}
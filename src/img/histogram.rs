@@ -0,0 +1,63 @@
+//! Luminance and per-channel histograms of the decoded image. They are
+//! computed on a worker thread so large images don't hold up the window.
+
+use std::{
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use image::DynamicImage;
+
+pub static BINS: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    pub luma: Vec<u32>,
+    pub red: Vec<u32>,
+    pub green: Vec<u32>,
+    pub blue: Vec<u32>,
+}
+
+impl Histogram {
+    /// Counts the 8 bit values of `image`. Luminance uses the Rec. 709
+    /// weights on the sRGB values.
+    pub fn compute(image: &DynamicImage) -> Self {
+        let mut histogram = Self {
+            luma: vec![0; BINS],
+            red: vec![0; BINS],
+            green: vec![0; BINS],
+            blue: vec![0; BINS],
+        };
+        for pixel in image.to_rgb8().pixels() {
+            let [r, g, b] = pixel.0;
+            let luma = (2126 * r as u32 + 7152 * g as u32 + 722 * b as u32) / 10000;
+            histogram.luma[luma as usize] += 1;
+            histogram.red[r as usize] += 1;
+            histogram.green[g as usize] += 1;
+            histogram.blue[b as usize] += 1;
+        }
+        histogram
+    }
+
+    /// Computes the histogram of `image` on a new thread, which calls
+    /// `notify` once the result is ready to receive.
+    pub fn spawn(image: DynamicImage, notify: impl FnOnce() + Send + 'static) -> Receiver<Self> {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            // the receiver is gone if another image was loaded meanwhile
+            if tx.send(Self::compute(&image)).is_ok() {
+                notify();
+            }
+        });
+        rx
+    }
+
+    /// The largest count of any channel, to scale the bars by.
+    pub fn peak(&self) -> u32 {
+        [&self.luma, &self.red, &self.green, &self.blue]
+            .into_iter()
+            .flat_map(|bins| bins.iter().copied())
+            .max()
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,361 @@
+//! ICC colour management. Embedded profiles are read from PNG, JPEG, WebP
+//! and TIFF files and rendered images are converted from them to the monitor
+//! profile, or sRGB when there is none. The monitor profile comes from
+//! `--monitor-profile` or the `_ICC_PROFILE` property on the root window.
+
+use std::{fs, io::Read, path::Path, sync::OnceLock};
+
+use anyhow::Result;
+use flate2::read::ZlibDecoder;
+use image::DynamicImage;
+use qcms::{DataType, Intent, Profile, Transform};
+use x11rb::{
+    connection::Connection,
+    protocol::xproto::{AtomEnum, ConnectionExt, Screen},
+};
+
+use crate::CLI;
+
+static MONITOR_PROFILE: OnceLock<Option<Box<Profile>>> = OnceLock::new();
+
+/// TIFF tag holding the embedded profile.
+const TIFF_ICC_TAG: u16 = 34675;
+
+/// Loads the monitor profile, preferring `--monitor-profile` over the one the
+/// desktop publishes on the root window.
+pub fn init_monitor_profile<C: Connection>(conn: &C, screen: &Screen) -> Result<()> {
+    let data = match &CLI.monitor_profile {
+        Some(path) => Some(fs::read(path)?),
+        None => root_window_profile(conn, screen)?,
+    };
+    let profile = data.and_then(|data| {
+        let profile = Profile::new_from_slice(&data, false);
+        match &profile {
+            Some(_) => info!("Using monitor profile {:?}", description(&data)),
+            None => err!("Ignoring malformed monitor profile"),
+        }
+        profile
+    });
+    MONITOR_PROFILE.get_or_init(|| profile);
+    Ok(())
+}
+
+fn root_window_profile<C: Connection>(conn: &C, screen: &Screen) -> Result<Option<Vec<u8>>> {
+    let atom = conn.intern_atom(true, b"_ICC_PROFILE")?.reply()?.atom;
+    if atom == x11rb::NONE {
+        return Ok(None);
+    }
+    let reply = conn
+        .get_property(false, screen.root, atom, AtomEnum::ANY, 0, u32::MAX / 4)?
+        .reply()?;
+    Ok((reply.format == 8 && !reply.value.is_empty()).then_some(reply.value))
+}
+
+/// Converts rendered images from the source profile to the monitor.
+pub struct ColorManagement {
+    transform: Transform,
+    pub profile_name: String,
+    /// The profile embedded in the image, if it has one.
+    pub embedded: Option<Vec<u8>>,
+    pub enabled: bool,
+}
+
+impl ColorManagement {
+    /// Sets up the conversion from the `embedded` profile of an image.
+    /// Returns `None` when there is nothing to convert: no embedded profile
+    /// and an sRGB monitor.
+    pub fn new(embedded: Option<Vec<u8>>) -> Option<Self> {
+        let monitor = MONITOR_PROFILE.get().and_then(|p| p.as_deref());
+        let embedded = embedded.and_then(|data| {
+            let profile = Profile::new_from_slice(&data, false)?;
+            let name = description(&data);
+            Some((profile, name, data))
+        });
+        if embedded.is_none() && monitor.is_none() {
+            return None;
+        }
+
+        let srgb = Profile::new_sRGB();
+        let (input, profile_name) = match &embedded {
+            Some((profile, name, _)) => (profile.as_ref(), name.clone()),
+            None => (srgb.as_ref(), "sRGB (assumed)".to_string()),
+        };
+        let output = monitor.unwrap_or(&srgb);
+        let Some(transform) = Transform::new(input, output, DataType::RGB8, Intent::Perceptual)
+        else {
+            err!("Can't convert from profile {profile_name:?}, showing colours unmanaged");
+            return None;
+        };
+        info!("Colour managing from profile {profile_name:?}");
+        Some(Self {
+            transform,
+            profile_name,
+            embedded: embedded.map(|(_, _, data)| data),
+            enabled: true,
+        })
+    }
+
+    pub fn apply(&self, data: &mut [u8]) {
+        if self.enabled {
+            self.transform.apply(data);
+        }
+    }
+}
+
+/// Converts `image` from its embedded `profile` to sRGB, which is what
+/// images without a profile are taken to be. The encoders can't embed a
+/// profile, so this keeps the colours when saving. qcms only transforms 8 bit
+/// data, so images with a profile other than sRGB are saved at 8 bits.
+pub fn to_srgb(image: DynamicImage, profile: &[u8]) -> DynamicImage {
+    let name = description(profile);
+    if name.contains("sRGB") {
+        return image;
+    }
+    let Some(input) = Profile::new_from_slice(profile, false) else {
+        return image;
+    };
+    let alpha = image.color().has_alpha();
+    let kind = if alpha {
+        DataType::RGBA8
+    } else {
+        DataType::RGB8
+    };
+    let srgb = Profile::new_sRGB();
+    let Some(transform) = Transform::new(&input, &srgb, kind, Intent::Perceptual) else {
+        info!("Can't convert from profile {name:?} to sRGB, saving it unconverted");
+        return image;
+    };
+    info!("Converting from profile {name:?} to sRGB");
+    if alpha {
+        let mut pixels = image.to_rgba8();
+        transform.apply(&mut pixels);
+        DynamicImage::ImageRgba8(pixels)
+    } else {
+        let mut pixels = image.to_rgb8();
+        transform.apply(&mut pixels);
+        DynamicImage::ImageRgb8(pixels)
+    }
+}
+
+/// Reads the embedded ICC profile of the image at `path`, if any.
+pub fn extract(path: &Path) -> Option<Vec<u8>> {
+    extract_from(&fs::read(path).ok()?)
+}
+
+/// Finds the embedded ICC profile in the encoded image `data`, if any.
+pub fn extract_from(data: &[u8]) -> Option<Vec<u8>> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        png_profile(data)
+    } else if data.starts_with(&[0xff, 0xd8]) {
+        jpeg_profile(data)
+    } else if data.len() > 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        webp_profile(data)
+    } else if data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") {
+        tiff_profile(data)
+    } else {
+        None
+    }
+}
+
+/// The `iCCP` chunk holds a name, a compression method and the zlib
+/// compressed profile.
+fn png_profile(data: &[u8]) -> Option<Vec<u8>> {
+    let mut pos = 8;
+    while pos + 8 <= data.len() {
+        let len = u32::from_be_bytes(data[pos..pos + 4].try_into().ok()?) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        let body = data.get(pos + 8..pos + 8 + len)?;
+        match kind {
+            b"iCCP" => {
+                let name_end = body.iter().position(|b| *b == 0)?;
+                let mut profile = vec![];
+                ZlibDecoder::new(body.get(name_end + 2..)?)
+                    .read_to_end(&mut profile)
+                    .ok()?;
+                return Some(profile);
+            }
+            b"IDAT" | b"IEND" => return None,
+            _ => pos += 12 + len,
+        }
+    }
+    None
+}
+
+/// Profiles are split over `APP2` segments tagged `ICC_PROFILE`, each with
+/// its sequence number.
+fn jpeg_profile(data: &[u8]) -> Option<Vec<u8>> {
+    let mut chunks = vec![];
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xff {
+        let marker = data[pos + 1];
+        // start of scan, the headers are over
+        if marker == 0xda {
+            break;
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let body = data.get(pos + 4..pos + 2 + len)?;
+        if marker == 0xe2 && body.starts_with(b"ICC_PROFILE\0") && body.len() > 14 {
+            chunks.push((body[12], &body[14..]));
+        }
+        pos += 2 + len;
+    }
+    if chunks.is_empty() {
+        return None;
+    }
+    chunks.sort_by_key(|(seq, _)| *seq);
+    Some(chunks.into_iter().flat_map(|(_, c)| c.to_vec()).collect())
+}
+
+fn webp_profile(data: &[u8]) -> Option<Vec<u8>> {
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let len = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().ok()?) as usize;
+        if &data[pos..pos + 4] == b"ICCP" {
+            return data.get(pos + 8..pos + 8 + len).map(<[u8]>::to_vec);
+        }
+        // chunks are padded to an even size
+        pos += 8 + len + (len & 1);
+    }
+    None
+}
+
+/// Looks for the profile tag in the first IFD.
+fn tiff_profile(data: &[u8]) -> Option<Vec<u8>> {
+    let le = data[0] == b'I';
+    let u16_at = |pos: usize| -> Option<u16> {
+        let b = data.get(pos..pos + 2)?.try_into().ok()?;
+        Some(if le {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    };
+    let u32_at = |pos: usize| -> Option<u32> {
+        let b = data.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if le {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    };
+
+    let ifd = u32_at(4)? as usize;
+    for i in 0..u16_at(ifd)? as usize {
+        let entry = ifd + 2 + i * 12;
+        if u16_at(entry)? == TIFF_ICC_TAG {
+            let len = u32_at(entry + 4)? as usize;
+            let offset = u32_at(entry + 8)? as usize;
+            return data.get(offset..offset + len).map(<[u8]>::to_vec);
+        }
+    }
+    None
+}
+
+/// Reads the profile description from its `desc` tag, which is either a
+/// v2 `desc` text or a v4 `mluc` UTF-16 string.
+pub fn description(profile: &[u8]) -> String {
+    let be32 = |pos: usize| -> Option<usize> {
+        Some(u32::from_be_bytes(profile.get(pos..pos + 4)?.try_into().ok()?) as usize)
+    };
+    let find = || -> Option<String> {
+        for i in 0..be32(128)? {
+            let entry = 132 + i * 12;
+            if profile.get(entry..entry + 4)? != b"desc" {
+                continue;
+            }
+            let (offset, len) = (be32(entry + 4)?, be32(entry + 8)?);
+            let tag = profile.get(offset..offset + len)?;
+            return match tag.get(..4)? {
+                b"desc" => {
+                    let n = be32(offset + 8)?;
+                    let text = tag.get(12..12 + n)?;
+                    Some(String::from_utf8_lossy(text).trim_end_matches('\0').into())
+                }
+                b"mluc" => {
+                    let (len, start) = (be32(offset + 20)?, be32(offset + 24)?);
+                    let units = tag
+                        .get(start..start + len)?
+                        .chunks_exact(2)
+                        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                        .collect::<Vec<_>>();
+                    Some(String::from_utf16_lossy(&units))
+                }
+                _ => None,
+            };
+        }
+        None
+    };
+    find()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unnamed".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    /// A profile whose only tag is a `desc` tag holding `tag`.
+    fn profile(tag: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; 128];
+        data.extend(1u32.to_be_bytes());
+        data.extend(b"desc");
+        data.extend(144u32.to_be_bytes());
+        data.extend((tag.len() as u32).to_be_bytes());
+        data.extend(tag);
+        data
+    }
+
+    #[test]
+    fn reads_v2_and_v4_descriptions() {
+        let mut desc = b"desc\0\0\0\0".to_vec();
+        desc.extend(10u32.to_be_bytes());
+        desc.extend(b"Adobe RGB\0");
+        assert_eq!(description(&profile(&desc)), "Adobe RGB");
+
+        let mut mluc = b"mluc\0\0\0\0".to_vec();
+        mluc.extend(1u32.to_be_bytes());
+        mluc.extend(12u32.to_be_bytes());
+        mluc.extend(b"enUS");
+        mluc.extend(6u32.to_be_bytes());
+        mluc.extend(28u32.to_be_bytes());
+        mluc.extend([0, b'P', 0, b'3', 0, b'!']);
+        assert_eq!(description(&profile(&mluc)), "P3!");
+    }
+
+    #[test]
+    fn survives_malformed_descriptions() {
+        for tag in [
+            &b""[..],
+            b"de",
+            b"desc",
+            b"desc\0\0\0\0\xff\xff\xff\xff",
+            b"mluc",
+        ] {
+            assert_eq!(description(&profile(tag)), "unnamed");
+        }
+        assert_eq!(description(&[0; 10]), "unnamed");
+    }
+
+    #[test]
+    fn finds_png_profiles() {
+        let icc = profile(b"text");
+        let mut compressed = ZlibEncoder::new(vec![], Compression::default());
+        compressed.write_all(&icc).unwrap();
+        let mut body = b"icc\0\0".to_vec();
+        body.extend(compressed.finish().unwrap());
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend((body.len() as u32).to_be_bytes());
+        png.extend(b"iCCP");
+        png.extend(body);
+        png.extend([0; 4]);
+        assert_eq!(extract_from(&png), Some(icc));
+
+        // a chunk running past the end of the file
+        png.truncate(png.len() - 10);
+        assert_eq!(extract_from(&png), None);
+        assert_eq!(extract_from(b"not an image"), None);
+    }
+}
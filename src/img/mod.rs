@@ -1,16 +1,42 @@
+pub mod adjust;
+pub mod channel;
+mod decoders;
+pub mod histogram;
+pub mod icc;
+pub mod pages;
+pub mod palette;
+#[cfg(feature = "raw")]
+mod raw;
+mod svg;
+pub mod tonemap;
+
 use anyhow::Result;
 use image::imageops::FilterType;
 use image::io::Reader as ImageReader;
+use image::{DynamicImage, Rgb, RgbImage, Rgba, RgbaImage};
 use lazy_static::{__Deref, lazy_static};
-use std::{borrow::Cow, fmt::Debug, fs::File, path::PathBuf};
+use resvg::usvg::Tree;
+use std::{borrow::Cow, fmt::Debug, fs::File, io::Cursor, path::Path};
 use x11rb::{
     connection::Connection,
     image::{BitsPerPixel, ColorComponent, Image, ImageOrder, PixelLayout, ScanlinePad},
-    protocol::xproto::Screen,
     rust_connection::RustConnection,
 };
 
-use crate::font::{FontDrawer, RenderLine, ToRenderLine};
+use crate::{
+    files::FileEntry,
+    font::{FontDrawer, RenderLine, ToRenderLine},
+    screen::PixelFormat,
+    CLI,
+};
+
+use self::{
+    adjust::Adjustments,
+    channel::Channel,
+    icc::ColorManagement,
+    pages::{PagedFormat, Pages},
+    tonemap::{Rgb10Image, Tone},
+};
 
 lazy_static! {
     static ref FOREIGN_PIXEL_LAYOUT: PixelLayout = PixelLayout::new(
@@ -18,10 +44,78 @@ lazy_static! {
         ColorComponent::new(8, 8).unwrap(),
         ColorComponent::new(8, 16).unwrap(),
     );
+    static ref FOREIGN_PIXEL_LAYOUT_10: PixelLayout = PixelLayout::new(
+        ColorComponent::new(10, 0).unwrap(),
+        ColorComponent::new(10, 10).unwrap(),
+        ColorComponent::new(10, 20).unwrap(),
+    );
+}
+
+/// Root depth of deep colour visuals, with 10 bits per channel.
+static DEEP_COLOR_DEPTH: u8 = 30;
+
+pub static MIN_ZOOM: f32 = 0.05;
+pub static MAX_ZOOM: f32 = 32.0;
+pub static ZOOM_STEP: f32 = 1.25;
+
+/// Upper bound for either side of the rendered image, to keep zooming in on
+/// large images from exhausting memory.
+static MAX_RENDER_SIZE: u32 = 8192;
+
+/// The decoded image, kept around so it can be re-rendered at other zoom
+/// levels.
+enum ImageSource {
+    Raster(DynamicImage),
+    Svg(Box<Tree>),
+}
+
+impl ImageSource {
+    /// The pixels at the original size. SVGs are rasterised for this.
+    fn pixels(&self) -> Result<Cow<'_, DynamicImage>> {
+        Ok(match self {
+            ImageSource::Raster(image) => Cow::Borrowed(image),
+            ImageSource::Svg(tree) => {
+                Cow::Owned(DynamicImage::ImageRgb8(svg::rasterize(tree, 1.0)?))
+            }
+        })
+    }
+}
+
+/// Rendered pixels, in 8 bits per channel or 10 for deep colour visuals.
+#[derive(Clone)]
+enum RenderBuffer {
+    Rgb8(RgbImage),
+    Rgb10(Rgb10Image),
+}
+
+impl RenderBuffer {
+    fn dimensions(&self) -> (u32, u32) {
+        match self {
+            RenderBuffer::Rgb8(buffer) => buffer.dimensions(),
+            RenderBuffer::Rgb10(buffer) => buffer.dimensions(),
+        }
+    }
+
+    fn into_rgb8(self) -> RgbImage {
+        match self {
+            RenderBuffer::Rgb8(buffer) => buffer,
+            RenderBuffer::Rgb10(buffer) => {
+                RgbImage::from_fn(buffer.width(), buffer.height(), |x, y| {
+                    Rgb(buffer.get_pixel(x, y).0.map(|v| (v >> 2) as u8))
+                })
+            }
+        }
+    }
 }
 
 pub struct MeviImage {
     pub inner: Image<'static>,
+    source: ImageSource,
+    pixel_format: PixelFormat,
+    /// Area the image is fitted into when no zoom is set.
+    fit_area: (u32, u32),
+    pub zoom: Option<f32>,
+    pub scale: f32,
     pub ow: u32,
     pub oh: u32,
     pub w: u16,
@@ -29,106 +123,670 @@ pub struct MeviImage {
     pub size: u64,
     pub path: String,
     pub format: String,
+    /// Bits per channel of the decoded image, e.g. `16-bit`.
+    pub depth: String,
+    pub tone: Tone,
+    pub look: Look,
+    /// The resized and tone mapped pixels before the adjustments and colour
+    /// management, so changing those doesn't resize the image again.
+    resized: RenderBuffer,
+    pub color: Option<ColorManagement>,
+    /// Sub-images of multi-page files, with the one being shown.
+    pub pages: Option<Pages>,
+    /// Format specific lines for the file info, e.g. camera settings.
+    pub details: Vec<String>,
 }
 
 impl Debug for MeviImage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "MeviImage {{ ow: {}, oh: {}, w: {}, h: {}, size: {}, path: {}, format: {} }}",
-            self.ow, self.oh, self.w, self.h, self.size, self.path, self.format
+            "MeviImage {{ ow: {}, oh: {}, w: {}, h: {}, scale: {}, size: {}, path: {}, format: {} }}",
+            self.ow, self.oh, self.w, self.h, self.scale, self.size, self.path, self.format
         )
     }
 }
 
-impl MeviImage {
-    pub fn new(
-        conn: &RustConnection,
-        screen: &Screen,
-        path: &PathBuf,
-        pixel_layout: PixelLayout,
-    ) -> Result<Self> {
+/// A decoded image along with what was found out about the file, before it
+/// is rendered.
+struct Decoded {
+    source: ImageSource,
+    format: String,
+    details: Vec<String>,
+    size: u64,
+    color: Option<ColorManagement>,
+    pages: Option<Pages>,
+}
+
+impl Decoded {
+    fn open(entry: &FileEntry) -> Result<Self> {
+        if let Some(image) = &entry.capture {
+            return Ok(Self {
+                source: ImageSource::Raster(image.as_ref().clone()),
+                format: "screen capture".into(),
+                details: vec![],
+                size: 0,
+                color: None,
+                pages: None,
+            });
+        }
+        match &entry.member {
+            Some(member) => Self::from_memory(&member.archive.read(&member.name)?),
+            None => Self::from_path(&entry.path),
+        }
+    }
+
+    fn from_path(path: &Path) -> Result<Self> {
         let size = {
             let f = File::open(path)?;
             let data = f.metadata()?;
             data.len() / 1024 // Kb
         };
 
-        let image = ImageReader::open(path)?.with_guessed_format()?;
+        let mut details = vec![];
+        // RAW files are TIFFs too, only the formats `image` decodes are paged
+        let mut pages = None;
+        let (source, format) = if svg::is_svg(path) {
+            (
+                ImageSource::Svg(Box::new(svg::load(path)?)),
+                "SVG".to_string(),
+            )
+        } else if let Some(fmt) = decoders::detect(path) {
+            let (image, extra) = decoders::decode(path, fmt)?;
+            details = extra;
+            (ImageSource::Raster(image), fmt.to_string())
+        } else {
+            pages = Pages::scan(path);
+            let cursor = pages::is_cursor(path);
+            match &pages {
+                // `image` doesn't recognise cursors, icons go through the
+                // same decoder as their other pages
+                Some(p) if p.format == PagedFormat::Ico => (
+                    ImageSource::Raster(p.decode(path, p.current)?),
+                    if cursor { "Cur" } else { "Ico" }.to_string(),
+                ),
+                None if cursor => (
+                    ImageSource::Raster(pages::decode_ico(path, 0)?),
+                    "Cur".to_string(),
+                ),
+                _ => {
+                    let image = ImageReader::open(path)?.with_guessed_format()?;
+                    let format = if let Some(fmt) = image.format() {
+                        format!("{fmt:?}")
+                    } else {
+                        "unknown".into()
+                    };
+                    (ImageSource::Raster(image.decode()?), format)
+                }
+            }
+        };
+        let color = match &source {
+            ImageSource::Raster(_) => ColorManagement::new(icc::extract(path)),
+            ImageSource::Svg(_) => None,
+        };
+        Ok(Self {
+            source,
+            format,
+            details,
+            size,
+            color,
+            pages,
+        })
+    }
+
+    /// Decodes an image read from an archive. Only raster formats the
+    /// `image` crate knows are supported there.
+    fn from_memory(data: &[u8]) -> Result<Self> {
+        let image = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
         let format = if let Some(fmt) = image.format() {
             format!("{fmt:?}")
         } else {
             "unknown".into()
         };
-        let mut image = image.decode()?;
-        let (ow, oh) = (image.width(), image.height());
+        Ok(Self {
+            source: ImageSource::Raster(image.decode()?),
+            format,
+            details: vec![],
+            size: data.len() as u64 / 1024, // Kb
+            color: ColorManagement::new(icc::extract_from(data)),
+            pages: None,
+        })
+    }
+}
 
-        let (sw, sh) = (
-            screen.width_in_pixels as u32,
-            screen.height_in_pixels as u32,
-        );
-        if ow > sw || oh > sh {
-            image = image.resize(sw, sh, FilterType::Lanczos3)
-        }
+/// Decodes the file at `path` without displaying it, for headless use.
+pub fn decode_file(path: &Path) -> Result<DynamicImage> {
+    let decoded = Decoded::from_path(path)?;
+    Ok(decoded.source.pixels()?.into_owned())
+}
 
-        let image_buffer = image.into_rgb8();
-        let (new_w, new_h) = (image_buffer.width() as u16, image_buffer.height() as u16);
+impl MeviImage {
+    /// Loads `entry`, reading it from its archive if it is in one, and
+    /// renders it with `look` fitted into `fit_area`.
+    pub fn open<C: Connection>(
+        conn: &C,
+        entry: &FileEntry,
+        fit_area: (u32, u32),
+        pixel_format: PixelFormat,
+        look: Look,
+    ) -> Result<Self> {
+        let decoded = Decoded::open(entry)?;
+        Self::from_decoded(conn, &entry.path, decoded, fit_area, pixel_format, look)
+    }
 
-        let image = Image::new(
-            new_w,
-            new_h,
-            ScanlinePad::Pad8,
-            24,
-            BitsPerPixel::B24,
-            ImageOrder::LsbFirst,
-            Cow::from(image_buffer.into_vec()),
-        )?;
+    /// Wraps pixels that weren't read from a file, e.g. a diff. `name`
+    /// stands in for the path.
+    pub fn from_image<C: Connection>(
+        conn: &C,
+        name: &str,
+        image: DynamicImage,
+        fit_area: (u32, u32),
+        pixel_format: PixelFormat,
+    ) -> Result<Self> {
+        let decoded = Decoded {
+            source: ImageSource::Raster(image),
+            format: "generated".into(),
+            details: vec![],
+            size: 0,
+            color: None,
+            pages: None,
+        };
+        Self::from_decoded(
+            conn,
+            Path::new(name),
+            decoded,
+            fit_area,
+            pixel_format,
+            Look::default(),
+        )
+    }
 
-        let image = image.reencode(*FOREIGN_PIXEL_LAYOUT, pixel_layout, conn.setup())?;
+    fn from_decoded<C: Connection>(
+        conn: &C,
+        path: &Path,
+        decoded: Decoded,
+        fit_area: (u32, u32),
+        pixel_format: PixelFormat,
+        look: Look,
+    ) -> Result<Self> {
+        let Decoded {
+            source,
+            format,
+            details,
+            size,
+            color,
+            pages,
+        } = decoded;
+        let (ow, oh) = match &source {
+            ImageSource::Raster(image) => (image.width(), image.height()),
+            ImageSource::Svg(tree) => svg::dimensions(tree),
+        };
+        let (depth, tone) = match &source {
+            ImageSource::Raster(image) => (
+                tonemap::describe_depth(image),
+                Tone::for_image(image, CLI.tonemap, CLI.exposure),
+            ),
+            ImageSource::Svg(_) => (
+                "8-bit".into(),
+                Tone {
+                    op: tonemap::ToneMap::Clip,
+                    exposure: 0.0,
+                },
+            ),
+        };
+
+        // like `shown_color`, a single channel is shown unmanaged
+        let shown_color = color.as_ref().filter(|_| look.channel == Channel::All);
+        let rendering = Rendering {
+            tone,
+            channel: look.channel,
+            managed: shown_color.is_some_and(|c| c.enabled),
+        };
+        let deep = is_deep(&pixel_format);
+        let (resized, scale) = prepare(&source, (ow, oh), None, rendering, deep, fit_area)?;
+        let (new_w, new_h) = resized.dimensions();
+        let image_buffer = finish(resized.clone(), look.adjustments, shown_color);
 
         let mevi_image = MeviImage {
-            inner: image.deref().to_owned(),
+            inner: to_x11_image(conn, image_buffer, &pixel_format)?,
+            source,
+            pixel_format,
+            fit_area,
+            zoom: None,
+            scale,
             ow,
             oh,
-            w: new_w,
-            h: new_h,
+            w: new_w as u16,
+            h: new_h as u16,
             size,
             path: path.to_str().unwrap().to_owned(),
             format,
+            depth,
+            tone,
+            look,
+            resized,
+            color,
+            pages,
+            details,
         };
         info!("Loaded image: {mevi_image:?}");
 
         Ok(mevi_image)
     }
+
+    /// The decoded pixels at the original size.
+    pub fn pixels(&self) -> Result<Cow<'_, DynamicImage>> {
+        self.source.pixels()
+    }
+
+    /// The pixels at the original size as they are shown: reduced to the
+    /// picked channel, tone mapped and adjusted, and in sRGB rather than the
+    /// monitor's colours. Unchanged images keep their depth, changed ones
+    /// have 8 bits per channel.
+    pub fn shown_pixels(&self) -> Result<DynamicImage> {
+        let pixels = self.pixels()?;
+        let isolated = self.look.channel.isolate(&pixels);
+        let image = isolated.as_ref().unwrap_or(&pixels);
+        if self.look.adjustments.is_identity() && self.tone.is_identity() {
+            return Ok(match isolated {
+                Some(channel) => channel,
+                None => self.to_srgb(pixels.into_owned()),
+            });
+        }
+
+        let mut buffer = tonemap::to_rgb8(image, self.tone);
+        self.look.adjustments.apply(&mut buffer, u8::MAX as u16);
+        if isolated.is_some() {
+            return Ok(DynamicImage::ImageRgb8(buffer));
+        }
+        if !pixels.color().has_alpha() {
+            return Ok(self.to_srgb(DynamicImage::ImageRgb8(buffer)));
+        }
+        // keep the transparency, which the adjustments don't touch
+        let alpha = pixels.to_rgba8();
+        let (w, h) = buffer.dimensions();
+        Ok(self.to_srgb(DynamicImage::ImageRgba8(RgbaImage::from_fn(
+            w,
+            h,
+            |x, y| {
+                let [r, g, b] = buffer.get_pixel(x, y).0;
+                Rgba([r, g, b, alpha.get_pixel(x, y).0[3]])
+            },
+        ))))
+    }
+
+    /// Converts `image`, pixels of this image, from its embedded profile to
+    /// sRGB for saving.
+    pub fn to_srgb(&self, image: DynamicImage) -> DynamicImage {
+        match self.color.as_ref().and_then(|c| c.embedded.as_deref()) {
+            Some(profile) => icc::to_srgb(image, profile),
+            None => image,
+        }
+    }
+
+    /// Sets the zoom level, where `None` fits the image to the screen, and
+    /// re-renders the image.
+    pub fn set_zoom<C: Connection>(&mut self, conn: &C, zoom: Option<f32>) -> Result<()> {
+        self.zoom = zoom.map(|z| z.clamp(MIN_ZOOM, MAX_ZOOM));
+        self.render(conn)
+    }
+
+    pub fn zoom_in<C: Connection>(&mut self, conn: &C) -> Result<()> {
+        self.set_zoom(conn, Some(self.scale * ZOOM_STEP))
+    }
+
+    pub fn zoom_out<C: Connection>(&mut self, conn: &C) -> Result<()> {
+        self.set_zoom(conn, Some(self.scale / ZOOM_STEP))
+    }
+
+    /// Cycles through the tone mapping operators and re-renders the image.
+    pub fn next_tone_map<C: Connection>(&mut self, conn: &C) -> Result<()> {
+        self.tone.op = self.tone.op.next();
+        self.render(conn)
+    }
+
+    pub fn exposure_up<C: Connection>(&mut self, conn: &C) -> Result<()> {
+        self.tone.adjust_exposure(tonemap::EXPOSURE_STEP);
+        self.render(conn)
+    }
+
+    pub fn exposure_down<C: Connection>(&mut self, conn: &C) -> Result<()> {
+        self.tone.adjust_exposure(-tonemap::EXPOSURE_STEP);
+        self.render(conn)
+    }
+
+    /// Decodes and shows page `index` of a multi-page file, fitted to the
+    /// screen.
+    pub fn set_page<C: Connection>(&mut self, conn: &C, index: usize) -> Result<()> {
+        let Some(pages) = &mut self.pages else {
+            return Ok(());
+        };
+        let image = pages.decode(Path::new(&self.path), index)?;
+        pages.current = index;
+        (self.ow, self.oh) = (image.width(), image.height());
+        self.depth = tonemap::describe_depth(&image);
+        self.source = ImageSource::Raster(image);
+        self.set_zoom(conn, None)
+    }
+
+    /// Switches between the colour managed and unmanaged rendering. Returns
+    /// false when the image isn't colour managed.
+    pub fn toggle_color_management<C: Connection>(&mut self, conn: &C) -> Result<bool> {
+        let Some(color) = &mut self.color else {
+            return Ok(false);
+        };
+        color.enabled = !color.enabled;
+        self.render(conn)?;
+        Ok(true)
+    }
+
+    /// Shows only `channel` of the image, in grey.
+    pub fn set_channel<C: Connection>(&mut self, conn: &C, channel: Channel) -> Result<()> {
+        self.look.channel = channel;
+        self.render(conn)
+    }
+
+    /// Changes the display adjustments, re-applying them to the already
+    /// resized pixels.
+    pub fn set_adjustments<C: Connection>(
+        &mut self,
+        conn: &C,
+        adjustments: Adjustments,
+    ) -> Result<()> {
+        self.look.adjustments = adjustments;
+        self.finish_render(conn)
+    }
+
+    /// The colour management to apply, none when showing a single channel.
+    fn shown_color(&self) -> Option<&ColorManagement> {
+        self.color
+            .as_ref()
+            .filter(|_| self.look.channel == Channel::All)
+    }
+
+    fn render<C: Connection>(&mut self, conn: &C) -> Result<()> {
+        let rendering = Rendering {
+            tone: self.tone,
+            channel: self.look.channel,
+            managed: self.shown_color().is_some_and(|c| c.enabled),
+        };
+        let (resized, scale) = prepare(
+            &self.source,
+            (self.ow, self.oh),
+            self.zoom,
+            rendering,
+            is_deep(&self.pixel_format),
+            self.fit_area,
+        )?;
+        let (w, h) = resized.dimensions();
+        self.w = w as u16;
+        self.h = h as u16;
+        self.scale = scale;
+        self.resized = resized;
+        info!("Rendered image at {}x{} (scale {scale})", self.w, self.h);
+        self.finish_render(conn)
+    }
+
+    /// Adjusts and colour manages a copy of the resized pixels and uploads
+    /// the result.
+    fn finish_render<C: Connection>(&mut self, conn: &C) -> Result<()> {
+        let buffer = finish(
+            self.resized.clone(),
+            self.look.adjustments,
+            self.shown_color(),
+        );
+        self.inner = to_x11_image(conn, buffer, &self.pixel_format)?;
+        Ok(())
+    }
+}
+
+/// What is shown of an image apart from its size and tone: all channels or
+/// a single one, and the display adjustments. It is carried over when
+/// another file is opened.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Look {
+    pub channel: Channel,
+    pub adjustments: Adjustments,
+}
+
+impl Default for Look {
+    fn default() -> Self {
+        Self {
+            channel: Channel::All,
+            adjustments: Adjustments::default(),
+        }
+    }
+}
+
+/// How the decoded pixels are turned into the resized buffer.
+#[derive(Clone, Copy)]
+struct Rendering {
+    tone: Tone,
+    channel: Channel,
+    /// Colour management is going to be applied, which works on 8 bits.
+    managed: bool,
+}
+
+/// Renders `source` at `zoom`, or scaled down to fit `(sw, sh)` when no zoom
+/// is set. Raster images are reduced to the channel of `rendering` if one is
+/// picked, then mapped to the output depth with its tone. High bit depth
+/// images keep 10 bits per channel when `deep` is set, unless they are going
+/// to be colour managed. Returns the buffer and the scale that was used.
+fn prepare(
+    source: &ImageSource,
+    (ow, oh): (u32, u32),
+    zoom: Option<f32>,
+    rendering: Rendering,
+    deep: bool,
+    (sw, sh): (u32, u32),
+) -> Result<(RenderBuffer, f32)> {
+    let Rendering {
+        tone,
+        channel,
+        managed,
+    } = rendering;
+    let (fw, fh) = (ow as f32, oh as f32);
+    let scale = match zoom {
+        Some(zoom) => zoom,
+        None => (sw as f32 / fw).min(sh as f32 / fh).min(1.0),
+    };
+    let scale = scale
+        .min(MAX_RENDER_SIZE as f32 / fw)
+        .min(MAX_RENDER_SIZE as f32 / fh);
+    let (w, h) = (
+        ((fw * scale).round() as u32).max(1),
+        ((fh * scale).round() as u32).max(1),
+    );
+
+    let image_buffer = match source {
+        ImageSource::Raster(image) => {
+            // smooth when shrinking, keep pixels crisp when magnifying
+            let filter = if scale < 1.0 {
+                FilterType::Lanczos3
+            } else {
+                FilterType::Nearest
+            };
+            let resized;
+            let image = if (w, h) == (ow, oh) {
+                image
+            } else {
+                resized = image.resize_exact(w, h, filter);
+                &resized
+            };
+            let isolated = channel.isolate(image);
+            let image = isolated.as_ref().unwrap_or(image);
+            if deep && !managed && tonemap::bit_depth(image) > 8 {
+                RenderBuffer::Rgb10(tonemap::to_rgb10(image, tone))
+            } else {
+                RenderBuffer::Rgb8(tonemap::to_rgb8(image, tone))
+            }
+        }
+        ImageSource::Svg(tree) => {
+            let image = DynamicImage::ImageRgb8(svg::rasterize(tree, scale)?);
+            RenderBuffer::Rgb8(match channel.isolate(&image) {
+                Some(isolated) => isolated.to_rgb8(),
+                None => image.into_rgb8(),
+            })
+        }
+    };
+    Ok((image_buffer, scale))
+}
+
+/// Applies `adjustments` to a prepared buffer, then `color` to 8 bit ones.
+fn finish(
+    buffer: RenderBuffer,
+    adjustments: Adjustments,
+    color: Option<&ColorManagement>,
+) -> RenderBuffer {
+    match buffer {
+        RenderBuffer::Rgb8(mut buffer) => {
+            adjustments.apply(&mut buffer, u8::MAX as u16);
+            if let Some(color) = color {
+                color.apply(&mut buffer);
+            }
+            RenderBuffer::Rgb8(buffer)
+        }
+        RenderBuffer::Rgb10(mut buffer) => {
+            adjustments.apply(&mut buffer, 1023);
+            RenderBuffer::Rgb10(buffer)
+        }
+    }
+}
+
+fn is_deep(pixel_format: &PixelFormat) -> bool {
+    matches!(pixel_format, PixelFormat::Direct(layout) if layout.depth() == DEEP_COLOR_DEPTH)
+}
+
+/// Converts an 8 bit buffer for drawables with `pixel_format` other than
+/// the image pixmaps, e.g. the wallpaper.
+pub fn to_x11_rgb8<C: Connection>(
+    conn: &C,
+    buffer: RgbImage,
+    pixel_format: &PixelFormat,
+) -> Result<Image<'static>> {
+    to_x11_image(conn, RenderBuffer::Rgb8(buffer), pixel_format)
+}
+
+fn to_x11_image<C: Connection>(
+    conn: &C,
+    image_buffer: RenderBuffer,
+    pixel_format: &PixelFormat,
+) -> Result<Image<'static>> {
+    let pixel_layout = match pixel_format {
+        PixelFormat::Direct(layout) => *layout,
+        PixelFormat::Indexed(palette) => return palette.quantise(conn, &image_buffer.into_rgb8()),
+    };
+    let (w, h) = image_buffer.dimensions();
+    let (image, foreign_layout) = match image_buffer {
+        RenderBuffer::Rgb8(buffer) => (
+            Image::new(
+                w as u16,
+                h as u16,
+                ScanlinePad::Pad8,
+                24,
+                BitsPerPixel::B24,
+                ImageOrder::LsbFirst,
+                Cow::from(buffer.into_vec()),
+            )?,
+            *FOREIGN_PIXEL_LAYOUT,
+        ),
+        RenderBuffer::Rgb10(buffer) => {
+            // pack each pixel into the low 30 bits of a little endian u32
+            let data = buffer
+                .pixels()
+                .flat_map(|px| {
+                    let [r, g, b] = px.0.map(u32::from);
+                    (r | g << 10 | b << 20).to_le_bytes()
+                })
+                .collect::<Vec<u8>>();
+            (
+                Image::new(
+                    w as u16,
+                    h as u16,
+                    ScanlinePad::Pad32,
+                    DEEP_COLOR_DEPTH,
+                    BitsPerPixel::B32,
+                    ImageOrder::LsbFirst,
+                    Cow::from(data),
+                )?,
+                *FOREIGN_PIXEL_LAYOUT_10,
+            )
+        }
+    };
+
+    let image = image.reencode(foreign_layout, pixel_layout, conn.setup())?;
+    Ok(image.deref().to_owned())
 }
 
 impl ToRenderLine for MeviImage {
     fn to_lines(&self, font_drawer: &FontDrawer) -> Vec<RenderLine> {
-        vec![
+        let mut lines = vec![
             RenderLine::new(font_drawer, format!("path: {}", self.path)),
             RenderLine::new(font_drawer, format!("dimensions: {}x{}", self.ow, self.oh)),
             RenderLine::new(font_drawer, format!("type: {}", self.format)),
+            RenderLine::new(font_drawer, format!("depth: {}", self.depth)),
             RenderLine::new(font_drawer, format!("size: {}Kb", self.size)),
-        ]
+            RenderLine::new(
+                font_drawer,
+                format!("zoom: {:.0}%", (self.scale * 100.0).round()),
+            ),
+        ];
+        if self.tone.op != tonemap::ToneMap::Clip || self.tone.exposure != 0.0 {
+            lines.push(RenderLine::new(
+                font_drawer,
+                format!("tone: {}, {:+.1} EV", self.tone.op, self.tone.exposure),
+            ));
+        }
+        if self.look.channel != Channel::All {
+            lines.push(RenderLine::new(
+                font_drawer,
+                format!("channel: {}", self.look.channel),
+            ));
+        }
+        if !self.look.adjustments.is_identity() {
+            lines.push(RenderLine::new(
+                font_drawer,
+                format!("adjust: {}", self.look.adjustments.describe()),
+            ));
+        }
+        if let Some(pages) = &self.pages {
+            lines.push(RenderLine::new(
+                font_drawer,
+                format!("page: {}/{}", pages.current + 1, pages.len()),
+            ));
+            // every page's size, the shown one in brackets
+            let sizes = pages
+                .dimensions
+                .iter()
+                .enumerate()
+                .map(|(i, (w, h))| match i == pages.current {
+                    true => format!("[{w}x{h}]"),
+                    false => format!("{w}x{h}"),
+                })
+                .collect::<Vec<_>>();
+            lines.push(RenderLine::new(
+                font_drawer,
+                format!("pages: {}", sizes.join(" ")),
+            ));
+        }
+        if let Some(color) = &self.color {
+            let state = if color.enabled { "" } else { " (unmanaged)" };
+            lines.push(RenderLine::new(
+                font_drawer,
+                format!("profile: {}{state}", color.profile_name),
+            ));
+        }
+        lines.extend(self.details.iter().map(|d| RenderLine::new(font_drawer, d)));
+        lines
     }
 }
 
-pub fn get_bg_image(conn: &RustConnection, pixel_layout: PixelLayout) -> Result<Image<'static>> {
+pub fn get_bg_image(conn: &RustConnection, pixel_format: &PixelFormat) -> Result<Image<'static>> {
     let bytes = include_bytes!("../resources/transparent-bg-smaller.png");
 
-    let image_buffer = image::load_from_memory(bytes)?.into_rgb8();
-    let image = Image::new(
-        image_buffer.width() as u16,
-        image_buffer.height() as u16,
-        ScanlinePad::Pad8,
-        24,
-        BitsPerPixel::B24,
-        ImageOrder::LsbFirst,
-        Cow::from(image_buffer.into_vec()),
-    )?;
-
-    let image = image.reencode(*FOREIGN_PIXEL_LAYOUT, pixel_layout, conn.setup())?;
+    let image_buffer = RenderBuffer::Rgb8(image::load_from_memory(bytes)?.into_rgb8());
+    let image = to_x11_image(conn, image_buffer, pixel_format)?;
     info!("Loaded background image");
 
-    Ok(image.deref().to_owned())
+    Ok(image)
 }
@@ -0,0 +1,289 @@
+//! Sub-images of multi-page TIFFs and of ICO/CUR files with several sizes.
+//! The first TIFF page is what the regular decoders show, icons are decoded
+//! entry by entry, and other pages are decoded on demand when paging through
+//! the file.
+
+use std::{
+    fs::{self, File},
+    io::{BufReader, Cursor, Read},
+    path::Path,
+};
+
+use anyhow::Result;
+use image::{
+    codecs::ico::IcoDecoder, DynamicImage, GrayAlphaImage, GrayImage, ImageBuffer, Luma, LumaA,
+    Rgb, RgbImage, Rgba, RgbaImage,
+};
+use thiserror::Error;
+use tiff::{
+    decoder::{Decoder, DecodingResult},
+    ColorType,
+};
+
+#[derive(Error, Debug)]
+pub enum PageError {
+    #[error("Page {0} is out of range")]
+    OutOfRange(usize),
+    #[error("Unsupported TIFF page layout: {0:?}")]
+    Unsupported(ColorType),
+    #[error("Malformed ICO directory")]
+    MalformedIco,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PagedFormat {
+    Tiff,
+    Ico,
+}
+
+/// The pages of a file and their dimensions.
+#[derive(Debug)]
+pub struct Pages {
+    pub format: PagedFormat,
+    pub dimensions: Vec<(u32, u32)>,
+    pub current: usize,
+}
+
+impl Pages {
+    /// Lists the pages of the file at `path`, or `None` when it doesn't have
+    /// more than one.
+    pub fn scan(path: &Path) -> Option<Self> {
+        let mut head = [0; 4];
+        File::open(path)
+            .and_then(|mut f| f.read_exact(&mut head))
+            .ok()?;
+        let (format, dimensions) = match &head {
+            b"II*\0" | b"MM\0*" => (PagedFormat::Tiff, tiff_dimensions(path)?),
+            [0, 0, 1 | 2, 0] => {
+                let data = fs::read(path).ok()?;
+                let entries = ico_entries(&data)?;
+                (
+                    PagedFormat::Ico,
+                    entries.iter().map(|e| e.dimensions).collect(),
+                )
+            }
+            _ => return None,
+        };
+        info!("Found {} {format:?} pages in {path:?}", dimensions.len());
+        // the ICO decoder shows the largest entry, so paging starts there
+        let current = match format {
+            PagedFormat::Tiff => 0,
+            PagedFormat::Ico => dimensions
+                .iter()
+                .enumerate()
+                .rev()
+                .max_by_key(|(_, (w, h))| w * h)
+                .map_or(0, |(i, _)| i),
+        };
+        (dimensions.len() > 1).then_some(Self {
+            format,
+            dimensions,
+            current,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.dimensions.len()
+    }
+
+    pub fn next_index(&self) -> usize {
+        (self.current + 1) % self.len()
+    }
+
+    pub fn prev_index(&self) -> usize {
+        self.current.checked_sub(1).unwrap_or(self.len() - 1)
+    }
+
+    /// Decodes page `index` of the file at `path`.
+    pub fn decode(&self, path: &Path, index: usize) -> Result<DynamicImage> {
+        if index >= self.len() {
+            Err(PageError::OutOfRange(index))?;
+        }
+        let image = match self.format {
+            // the regular decoder handles more layouts for the first page
+            PagedFormat::Tiff if index == 0 => image::open(path)?,
+            PagedFormat::Tiff => decode_tiff(path, index)?,
+            PagedFormat::Ico => decode_ico(path, index)?,
+        };
+        info!("Decoded page {index} of {path:?}");
+        Ok(image)
+    }
+}
+
+fn tiff_dimensions(path: &Path) -> Option<Vec<(u32, u32)>> {
+    let mut decoder = Decoder::new(BufReader::new(File::open(path).ok()?)).ok()?;
+    let mut dimensions = vec![decoder.dimensions().ok()?];
+    while decoder.more_images() {
+        decoder.next_image().ok()?;
+        dimensions.push(decoder.dimensions().ok()?);
+    }
+    Some(dimensions)
+}
+
+fn decode_tiff(path: &Path, index: usize) -> Result<DynamicImage> {
+    let mut decoder = Decoder::new(BufReader::new(File::open(path)?))?;
+    decoder.seek_to_image(index)?;
+    let (w, h) = decoder.dimensions()?;
+    let color = decoder.colortype()?;
+
+    let image = match (color, decoder.read_image()?) {
+        (ColorType::Gray(8), DecodingResult::U8(d)) => {
+            GrayImage::from_raw(w, h, d).map(DynamicImage::ImageLuma8)
+        }
+        (ColorType::GrayA(8), DecodingResult::U8(d)) => {
+            GrayAlphaImage::from_raw(w, h, d).map(DynamicImage::ImageLumaA8)
+        }
+        (ColorType::RGB(8), DecodingResult::U8(d)) => {
+            RgbImage::from_raw(w, h, d).map(DynamicImage::ImageRgb8)
+        }
+        (ColorType::RGBA(8), DecodingResult::U8(d)) => {
+            RgbaImage::from_raw(w, h, d).map(DynamicImage::ImageRgba8)
+        }
+        (ColorType::Gray(16), DecodingResult::U16(d)) => {
+            ImageBuffer::<Luma<u16>, _>::from_raw(w, h, d).map(DynamicImage::ImageLuma16)
+        }
+        (ColorType::GrayA(16), DecodingResult::U16(d)) => {
+            ImageBuffer::<LumaA<u16>, _>::from_raw(w, h, d).map(DynamicImage::ImageLumaA16)
+        }
+        (ColorType::RGB(16), DecodingResult::U16(d)) => {
+            ImageBuffer::<Rgb<u16>, _>::from_raw(w, h, d).map(DynamicImage::ImageRgb16)
+        }
+        (ColorType::RGBA(16), DecodingResult::U16(d)) => {
+            ImageBuffer::<Rgba<u16>, _>::from_raw(w, h, d).map(DynamicImage::ImageRgba16)
+        }
+        (ColorType::RGB(32), DecodingResult::F32(d)) => {
+            ImageBuffer::<Rgb<f32>, _>::from_raw(w, h, d).map(DynamicImage::ImageRgb32F)
+        }
+        (ColorType::RGBA(32), DecodingResult::F32(d)) => {
+            ImageBuffer::<Rgba<f32>, _>::from_raw(w, h, d).map(DynamicImage::ImageRgba32F)
+        }
+        _ => None,
+    };
+    Ok(image.ok_or(PageError::Unsupported(color))?)
+}
+
+struct IcoEntry {
+    offset: usize,
+    len: usize,
+    dimensions: (u32, u32),
+}
+
+/// Reads the ICO/CUR directory. A zero size in the directory stands for 256
+/// pixels.
+fn ico_entries(data: &[u8]) -> Option<Vec<IcoEntry>> {
+    let count = u16::from_le_bytes([*data.get(4)?, *data.get(5)?]) as usize;
+    let mut entries = vec![];
+    for i in 0..count {
+        let entry = data.get(6 + i * 16..6 + (i + 1) * 16)?;
+        let side = |b: u8| if b == 0 { 256 } else { b as u32 };
+        let len = u32::from_le_bytes(entry[8..12].try_into().ok()?) as usize;
+        let offset = u32::from_le_bytes(entry[12..16].try_into().ok()?) as usize;
+        entries.push(IcoEntry {
+            offset,
+            len,
+            dimensions: (side(entry[0]), side(entry[1])),
+        });
+    }
+    Some(entries)
+}
+
+/// Whether the file at `path` is a CUR cursor, which `image` only decodes
+/// when told it's an icon.
+pub fn is_cursor(path: &Path) -> bool {
+    let mut head = [0; 4];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut head))
+        .is_ok_and(|_| head == [0, 0, 2, 0])
+}
+
+/// Decodes a single entry by handing the `image` ICO decoder a copy of the
+/// file that only lists that entry.
+pub fn decode_ico(path: &Path, index: usize) -> Result<DynamicImage> {
+    let data = fs::read(path)?;
+    let entries = ico_entries(&data).ok_or(PageError::MalformedIco)?;
+    let entry = entries.get(index).ok_or(PageError::OutOfRange(index))?;
+    let image = data
+        .get(entry.offset..entry.offset + entry.len)
+        .ok_or(PageError::MalformedIco)?;
+
+    let mut single = vec![0, 0, 1, 0, 1, 0];
+    single.extend_from_slice(&data[6 + index * 16..6 + index * 16 + 12]);
+    single.extend_from_slice(&22u32.to_le_bytes());
+    single.extend_from_slice(image);
+    Ok(DynamicImage::from_decoder(IcoDecoder::new(Cursor::new(
+        single,
+    ))?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{codecs::png::PngEncoder, ColorType, ImageEncoder};
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    /// An icon of kind 1 (ICO) or 2 (CUR) with a PNG entry of each size.
+    fn icon(kind: u8, sizes: &[u32]) -> Vec<u8> {
+        let mut data = vec![0, 0, kind, 0, sizes.len() as u8, 0];
+        let mut images = vec![];
+        let mut offset = 6 + sizes.len() * 16;
+        for &size in sizes {
+            let mut png = vec![];
+            let pixels = vec![0; (size * size * 4) as usize];
+            PngEncoder::new(&mut png)
+                .write_image(&pixels, size, size, ColorType::Rgba8)
+                .unwrap();
+            data.extend([size as u8, size as u8, 0, 0, 1, 0, 32, 0]);
+            data.extend((png.len() as u32).to_le_bytes());
+            data.extend((offset as u32).to_le_bytes());
+            offset += png.len();
+            images.extend(png);
+        }
+        data.extend(images);
+        data
+    }
+
+    fn write(dir: &TempDir, name: &str, data: &[u8]) -> PathBuf {
+        let path = dir.path().join(name);
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn pages_icons_from_the_largest_entry() {
+        let dir = TempDir::new().unwrap();
+        for kind in [1, 2] {
+            let path = write(&dir, "icon", &icon(kind, &[16, 48, 32]));
+            let pages = Pages::scan(&path).unwrap();
+            assert_eq!(pages.format, PagedFormat::Ico);
+            assert_eq!(pages.dimensions, vec![(16, 16), (48, 48), (32, 32)]);
+            assert_eq!(pages.current, 1);
+            assert_eq!(pages.decode(&path, 2).unwrap().width(), 32);
+            assert!(pages.decode(&path, 3).is_err());
+            assert_eq!(is_cursor(&path), kind == 2);
+        }
+    }
+
+    #[test]
+    fn decodes_single_entry_cursors() {
+        let dir = TempDir::new().unwrap();
+        let path = write(&dir, "x.cur", &icon(2, &[24]));
+        assert!(Pages::scan(&path).is_none());
+        let image = crate::img::decode_file(&path).unwrap();
+        assert_eq!((image.width(), image.height()), (24, 24));
+    }
+
+    #[test]
+    fn rejects_truncated_directories() {
+        let mut data = icon(1, &[16, 32]);
+        assert_eq!(ico_entries(&data).unwrap().len(), 2);
+        data.truncate(30);
+        assert!(ico_entries(&data).is_none());
+        assert!(ico_entries(&[0, 0, 1]).is_none());
+        // a size byte of 0 stands for 256
+        let data = [
+            0, 0, 1, 0, 1, 0, 0, 0, 0, 0, 1, 0, 32, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        assert_eq!(ico_entries(&data).unwrap()[0].dimensions, (256, 256));
+    }
+}
@@ -0,0 +1,198 @@
+//! Output for visuals without true colour: PseudoColor, StaticColor,
+//! GrayScale and StaticGray. A palette of colour cube (or grey ramp) entries
+//! is allocated in the default colormap and images are quantised to it with
+//! Floyd-Steinberg dithering.
+
+use anyhow::Result;
+use image::RgbImage;
+use x11rb::{
+    connection::Connection,
+    image::Image,
+    protocol::xproto::{ConnectionExt, Screen, VisualClass, Visualtype},
+};
+
+/// Upper bound on the entries taken from the colormap, so other clients
+/// still find free cells on 8 bit displays.
+static MAX_ENTRIES: usize = 216;
+
+/// Bits per channel of the lookup table from colour to palette index.
+static LUT_BITS: u32 = 5;
+
+pub struct Palette {
+    pub depth: u8,
+    pub gray: bool,
+    /// Pixel values and the colours the server actually gave us.
+    entries: Vec<(u32, [u8; 3])>,
+    /// Index into `entries` of the nearest colour, for each 15 bit colour.
+    lut: Vec<u8>,
+}
+
+impl std::fmt::Debug for Palette {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Palette {{ depth: {}, gray: {}, entries: {} }}",
+            self.depth,
+            self.gray,
+            self.entries.len()
+        )
+    }
+}
+
+impl Palette {
+    /// Allocates the palette in the default colormap of `screen`. Requests
+    /// the colormap can't satisfy are skipped, read-only colormaps hand out
+    /// their closest existing colour.
+    pub fn allocate<C: Connection>(
+        conn: &C,
+        screen: &Screen,
+        visual: &Visualtype,
+        depth: u8,
+    ) -> Result<Self> {
+        let gray = matches!(
+            visual.class,
+            VisualClass::GRAY_SCALE | VisualClass::STATIC_GRAY
+        );
+        let cells = (1usize << depth.min(16)).min(MAX_ENTRIES);
+        let requests = if gray {
+            ramp(cells)
+                .into_iter()
+                .map(|v| [v, v, v])
+                .collect::<Vec<_>>()
+        } else {
+            let n = (1..).take_while(|n| n * n * n <= cells).last().unwrap_or(1);
+            let ramp = ramp(n);
+            let mut colors = vec![];
+            for &r in &ramp {
+                for &g in &ramp {
+                    for &b in &ramp {
+                        colors.push([r, g, b]);
+                    }
+                }
+            }
+            colors
+        };
+
+        let cookies = requests
+            .iter()
+            .map(|[r, g, b]| {
+                let [r, g, b] = [r, g, b].map(|v| u16::from(*v) * 257);
+                conn.alloc_color(screen.default_colormap, r, g, b)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut entries: Vec<(u32, [u8; 3])> = vec![];
+        for cookie in cookies {
+            let Ok(reply) = cookie.reply() else {
+                continue;
+            };
+            // read-only colormaps may return the same cell for several requests
+            if entries.iter().any(|(pixel, _)| *pixel == reply.pixel) {
+                continue;
+            }
+            let color = [reply.red, reply.green, reply.blue].map(|v| (v >> 8) as u8);
+            entries.push((reply.pixel, color));
+        }
+        if entries.is_empty() {
+            // nothing could be allocated, fall back to the two guaranteed pixels
+            entries.push((screen.black_pixel, [0, 0, 0]));
+            entries.push((screen.white_pixel, [255, 255, 255]));
+        }
+        info!(
+            "Allocated {} of {} palette entries in colormap {}",
+            entries.len(),
+            requests.len(),
+            screen.default_colormap
+        );
+
+        let lut = build_lut(&entries, gray);
+        Ok(Self {
+            depth,
+            gray,
+            entries,
+            lut,
+        })
+    }
+
+    fn nearest(&self, [r, g, b]: [i32; 3]) -> usize {
+        let shift = 8 - LUT_BITS;
+        let [r, g, b] = [r, g, b].map(|v| (v.clamp(0, 255) as usize) >> shift);
+        self.lut[(r << (2 * LUT_BITS)) | (g << LUT_BITS) | b] as usize
+    }
+
+    /// Quantises `buffer` to the palette with Floyd-Steinberg dithering and
+    /// returns it as an image in the server's native format.
+    pub fn quantise<C: Connection>(&self, conn: &C, buffer: &RgbImage) -> Result<Image<'static>> {
+        let (w, h) = buffer.dimensions();
+        let mut image = Image::allocate_native(w as u16, h as u16, self.depth, conn.setup())?;
+
+        // error carried over to the current and next row
+        let mut errors = vec![[0i32; 3]; (w as usize + 2) * 2];
+        let row = w as usize + 2;
+        for y in 0..h {
+            let (current, next) = errors.split_at_mut(row);
+            for x in 0..w {
+                let px = buffer.get_pixel(x, y).0;
+                let mut wanted = [0; 3];
+                for c in 0..3 {
+                    wanted[c] = i32::from(px[c]) + current[x as usize + 1][c] / 16;
+                }
+                if self.gray {
+                    let luma = (wanted[0] * 77 + wanted[1] * 150 + wanted[2] * 29) >> 8;
+                    wanted = [luma; 3];
+                }
+
+                let (pixel, color) = self.entries[self.nearest(wanted)];
+                image.put_pixel(x as u16, y as u16, pixel);
+
+                let x = x as usize + 1;
+                for c in 0..3 {
+                    let e = wanted[c] - i32::from(color[c]);
+                    current[x + 1][c] += e * 7;
+                    next[x - 1][c] += e * 3;
+                    next[x][c] += e * 5;
+                    next[x + 1][c] += e;
+                }
+            }
+            errors.rotate_left(row);
+            errors[row..].fill([0; 3]);
+        }
+        Ok(image)
+    }
+}
+
+/// `n` evenly spaced levels from 0 to 255.
+fn ramp(n: usize) -> Vec<u8> {
+    if n < 2 {
+        return vec![0];
+    }
+    (0..n).map(|i| (i * 255 / (n - 1)) as u8).collect()
+}
+
+fn build_lut(entries: &[(u32, [u8; 3])], gray: bool) -> Vec<u8> {
+    let size = 1 << LUT_BITS;
+    let step = 256 / size;
+    let mut lut = Vec::with_capacity(size * size * size);
+    for r in 0..size {
+        for g in 0..size {
+            for b in 0..size {
+                // centre of the bucket
+                let mut want = [r, g, b].map(|v| (v * step + step / 2) as i32);
+                if gray {
+                    want = [(want[0] * 77 + want[1] * 150 + want[2] * 29) >> 8; 3];
+                }
+                let nearest = entries
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, (_, color))| {
+                        (0..3)
+                            .map(|c| (want[c] - i32::from(color[c])).pow(2))
+                            .sum::<i32>()
+                    })
+                    .map(|(i, _)| i)
+                    .unwrap_or(0);
+                lut.push(nearest as u8);
+            }
+        }
+    }
+    lut
+}
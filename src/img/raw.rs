@@ -0,0 +1,571 @@
+//! Camera RAW support for the TIFF based formats (CR2, NEF, ARW, DNG, ...).
+//! The largest embedded JPEG preview is shown by default. With
+//! `--raw-demosaic` uncompressed CFA data is demosaiced instead, falling back
+//! to the preview for compressed raw data.
+
+use std::{collections::HashSet, fs, path::Path};
+
+use anyhow::Result;
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use thiserror::Error;
+
+use crate::CLI;
+
+#[derive(Error, Debug)]
+pub enum RawError {
+    #[error("Not a TIFF based RAW file")]
+    NotTiff,
+    #[error("No decodable preview found")]
+    NoPreview,
+    #[error("Unsupported raw data: {0}")]
+    Unsupported(&'static str),
+}
+
+mod tag {
+    pub const NEW_SUBFILE_TYPE: u16 = 0x00fe;
+    pub const IMAGE_WIDTH: u16 = 0x0100;
+    pub const IMAGE_LENGTH: u16 = 0x0101;
+    pub const BITS_PER_SAMPLE: u16 = 0x0102;
+    pub const COMPRESSION: u16 = 0x0103;
+    pub const PHOTOMETRIC: u16 = 0x0106;
+    pub const MAKE: u16 = 0x010f;
+    pub const MODEL: u16 = 0x0110;
+    pub const STRIP_OFFSETS: u16 = 0x0111;
+    pub const ORIENTATION: u16 = 0x0112;
+    pub const SAMPLES_PER_PIXEL: u16 = 0x0115;
+    pub const STRIP_BYTE_COUNTS: u16 = 0x0117;
+    pub const SUB_IFDS: u16 = 0x014a;
+    pub const JPEG_OFFSET: u16 = 0x0201;
+    pub const JPEG_LENGTH: u16 = 0x0202;
+    pub const CFA_REPEAT_PATTERN_DIM: u16 = 0x828d;
+    pub const CFA_PATTERN: u16 = 0x828e;
+    pub const EXPOSURE_TIME: u16 = 0x829a;
+    pub const F_NUMBER: u16 = 0x829d;
+    pub const EXIF_IFD: u16 = 0x8769;
+    pub const ISO: u16 = 0x8827;
+    pub const FOCAL_LENGTH: u16 = 0x920a;
+    pub const BLACK_LEVEL: u16 = 0xc61a;
+    pub const WHITE_LEVEL: u16 = 0xc61d;
+    pub const AS_SHOT_NEUTRAL: u16 = 0xc628;
+}
+
+const PHOTOMETRIC_CFA: u32 = 32803;
+const PHOTOMETRIC_LINEAR_RAW: u32 = 34892;
+
+/// Decodes the RAW file at `path`, returning the image and lines describing
+/// the camera settings.
+pub fn decode(path: &Path) -> Result<(DynamicImage, Vec<String>)> {
+    let data = fs::read(path)?;
+    let tiff = Tiff::new(&data).ok_or(RawError::NotTiff)?;
+    let ifds = tiff.ifds();
+    let ifd0 = ifds.first().ok_or(RawError::NotTiff)?;
+
+    let mut details = camera_details(&tiff, &ifds);
+
+    let demosaiced = if CLI.raw_demosaic {
+        match demosaic(&tiff, &ifds) {
+            Ok(image) => Some(image),
+            Err(e) => {
+                err!("Failed to demosaic {path:?}: {e}");
+                details.push(format!("raw: embedded preview ({e})"));
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let image = match demosaiced {
+        Some(image) => {
+            details.push("raw: demosaiced".into());
+            image
+        }
+        None => {
+            let image = largest_preview(&tiff, &ifds)?;
+            if !CLI.raw_demosaic {
+                details.push("raw: embedded preview".into());
+            }
+            image
+        }
+    };
+
+    let image = match ifd0.uint(&tiff, tag::ORIENTATION) {
+        Some(3) => image.rotate180(),
+        Some(6) => image.rotate90(),
+        Some(8) => image.rotate270(),
+        _ => image,
+    };
+    info!("Decoded RAW image {path:?}: {details:?}");
+    Ok((image, details))
+}
+
+fn camera_details(tiff: &Tiff, ifds: &[Ifd]) -> Vec<String> {
+    let find = |t| ifds.iter().find(|ifd| ifd.get(t).is_some());
+    let mut details = vec![];
+
+    let make = find(tag::MAKE).and_then(|i| i.ascii(tiff, tag::MAKE));
+    let model = find(tag::MODEL).and_then(|i| i.ascii(tiff, tag::MODEL));
+    match (make, model) {
+        // most models already start with the make
+        (Some(make), Some(model)) if model.starts_with(&make) => {
+            details.push(format!("camera: {model}"))
+        }
+        (Some(make), Some(model)) => details.push(format!("camera: {make} {model}")),
+        (Some(name), None) | (None, Some(name)) => details.push(format!("camera: {name}")),
+        (None, None) => {}
+    }
+
+    let mut exposure = vec![];
+    if let Some(t) = find(tag::EXPOSURE_TIME).and_then(|i| i.rational(tiff, tag::EXPOSURE_TIME)) {
+        if t > 0.0 && t < 1.0 {
+            exposure.push(format!("1/{:.0}s", 1.0 / t));
+        } else {
+            exposure.push(format!("{t}s"));
+        }
+    }
+    if let Some(f) = find(tag::F_NUMBER).and_then(|i| i.rational(tiff, tag::F_NUMBER)) {
+        exposure.push(format!("f/{f:.1}"));
+    }
+    if let Some(iso) = find(tag::ISO).and_then(|i| i.uint(tiff, tag::ISO)) {
+        exposure.push(format!("ISO {iso}"));
+    }
+    if let Some(fl) = find(tag::FOCAL_LENGTH).and_then(|i| i.rational(tiff, tag::FOCAL_LENGTH)) {
+        exposure.push(format!("{fl:.0}mm"));
+    }
+    if !exposure.is_empty() {
+        details.push(format!("exposure: {}", exposure.join(" ")));
+    }
+    details
+}
+
+/// Tries the embedded JPEGs from largest to smallest until one decodes.
+/// Lossless JPEG raw data can show up as a candidate and will fail here.
+fn largest_preview(tiff: &Tiff, ifds: &[Ifd]) -> Result<DynamicImage> {
+    let mut candidates = vec![];
+    for ifd in ifds {
+        if let (Some(offset), Some(len)) = (
+            ifd.uint(tiff, tag::JPEG_OFFSET),
+            ifd.uint(tiff, tag::JPEG_LENGTH),
+        ) {
+            candidates.push((offset as usize, len as usize));
+        }
+
+        let photometric = ifd.uint(tiff, tag::PHOTOMETRIC);
+        let is_jpeg = matches!(ifd.uint(tiff, tag::COMPRESSION), Some(6 | 7));
+        let is_raw = matches!(photometric, Some(PHOTOMETRIC_CFA | PHOTOMETRIC_LINEAR_RAW));
+        let offsets = ifd.uints(tiff, tag::STRIP_OFFSETS);
+        let lens = ifd.uints(tiff, tag::STRIP_BYTE_COUNTS);
+        if is_jpeg && !is_raw && offsets.len() == 1 && lens.len() == 1 {
+            candidates.push((offsets[0] as usize, lens[0] as usize));
+        }
+    }
+    candidates.sort_by_key(|(_, len)| std::cmp::Reverse(*len));
+    info!("Found {} RAW preview candidates", candidates.len());
+
+    for (offset, len) in candidates {
+        let Some(jpeg) = tiff.data.get(offset..offset + len) else {
+            continue;
+        };
+        if !jpeg.starts_with(&[0xff, 0xd8]) {
+            continue;
+        }
+        match image::load_from_memory_with_format(jpeg, ImageFormat::Jpeg) {
+            Ok(image) => return Ok(image),
+            Err(e) => info!("Skipping RAW preview at {offset}: {e}"),
+        }
+    }
+    Err(RawError::NoPreview.into())
+}
+
+/// Bilinear demosaic of uncompressed 8 or 16 bit CFA data, with the as-shot
+/// white balance and an sRGB-like gamma applied. No camera colour matrix is
+/// used, so colours are approximate.
+fn demosaic(tiff: &Tiff, ifds: &[Ifd]) -> Result<DynamicImage, RawError> {
+    let ifd = ifds
+        .iter()
+        .filter(|ifd| ifd.uint(tiff, tag::NEW_SUBFILE_TYPE).unwrap_or(0) == 0)
+        .find(|ifd| ifd.uint(tiff, tag::PHOTOMETRIC) == Some(PHOTOMETRIC_CFA))
+        .ok_or(RawError::Unsupported("no CFA image"))?;
+
+    if ifd.uint(tiff, tag::COMPRESSION).unwrap_or(1) != 1 {
+        return Err(RawError::Unsupported("compressed"));
+    }
+    if ifd.uint(tiff, tag::SAMPLES_PER_PIXEL).unwrap_or(1) != 1 {
+        return Err(RawError::Unsupported("multiple samples per pixel"));
+    }
+    let bits = ifd.uint(tiff, tag::BITS_PER_SAMPLE).unwrap_or(16);
+    if bits != 8 && bits != 16 {
+        return Err(RawError::Unsupported("packed samples"));
+    }
+    if ifd
+        .uints(tiff, tag::CFA_REPEAT_PATTERN_DIM)
+        .iter()
+        .any(|d| *d != 2)
+    {
+        return Err(RawError::Unsupported("non 2x2 CFA pattern"));
+    }
+
+    let w = ifd.uint(tiff, tag::IMAGE_WIDTH).ok_or(RawError::NotTiff)? as usize;
+    let h = ifd.uint(tiff, tag::IMAGE_LENGTH).ok_or(RawError::NotTiff)? as usize;
+
+    // 0 = red, 1 = green, 2 = blue, RGGB unless stated otherwise
+    let pattern = match ifd.bytes(tiff, tag::CFA_PATTERN) {
+        Some(p) if p.len() == 4 && p.iter().all(|c| *c < 3) => [p[0], p[1], p[2], p[3]],
+        _ => [0, 1, 1, 2],
+    };
+
+    // the size comes straight from the file, so it's checked against the
+    // strip data before anything is allocated for it
+    let offsets = ifd.uints(tiff, tag::STRIP_OFFSETS);
+    let lens = ifd.uints(tiff, tag::STRIP_BYTE_COUNTS);
+    let count = w
+        .checked_mul(h)
+        .filter(|count| *count > 0)
+        .ok_or(RawError::Unsupported("invalid image size"))?;
+    let strip_bytes = lens.iter().map(|len| *len as u64).sum::<u64>();
+    if strip_bytes / (bits as u64 / 8) < count as u64 {
+        return Err(RawError::Unsupported("truncated strips"));
+    }
+
+    let mut samples = Vec::with_capacity(count);
+    for (offset, len) in offsets.iter().zip(lens.iter()) {
+        let (offset, len) = (*offset as usize, *len as usize);
+        let strip = tiff
+            .data
+            .get(offset..offset + len)
+            .ok_or(RawError::Unsupported("truncated strips"))?;
+        if bits == 8 {
+            samples.extend(strip.iter().map(|b| *b as f32));
+        } else {
+            samples.extend(strip.chunks_exact(2).map(|b| tiff.read_u16(b) as f32));
+        }
+    }
+    if samples.len() < count {
+        return Err(RawError::Unsupported("truncated strips"));
+    }
+
+    let black = ifd
+        .rational(tiff, tag::BLACK_LEVEL)
+        .or_else(|| ifd.uint(tiff, tag::BLACK_LEVEL).map(|b| b as f64))
+        .unwrap_or(0.0) as f32;
+    let white = ifd.uint(tiff, tag::WHITE_LEVEL).unwrap_or((1 << bits) - 1) as f32;
+    let neutral = ifds
+        .iter()
+        .find_map(|i| i.rationals(tiff, tag::AS_SHOT_NEUTRAL))
+        .filter(|n| n.len() == 3 && n.iter().all(|v| *v > 0.0))
+        .map(|n| [(n[1] / n[0]) as f32, 1.0, (n[1] / n[2]) as f32])
+        .unwrap_or([1.0, 1.0, 1.0]);
+
+    let color_at = |x: usize, y: usize| pattern[(y % 2) * 2 + x % 2] as usize;
+    let mut image = RgbImage::new(w as u32, h as u32);
+    for y in 0..h {
+        for x in 0..w {
+            let mut sums = [0.0f32; 3];
+            let mut counts = [0u32; 3];
+            for ny in y.saturating_sub(1)..(y + 2).min(h) {
+                for nx in x.saturating_sub(1)..(x + 2).min(w) {
+                    let c = color_at(nx, ny);
+                    sums[c] += samples[ny * w + nx];
+                    counts[c] += 1;
+                }
+            }
+
+            let own = color_at(x, y);
+            let mut px = [0u8; 3];
+            for c in 0..3 {
+                let v = if c == own {
+                    samples[y * w + x]
+                } else if counts[c] > 0 {
+                    sums[c] / counts[c] as f32
+                } else {
+                    0.0
+                };
+                let v = ((v - black) / (white - black) * neutral[c]).clamp(0.0, 1.0);
+                px[c] = (v.powf(1.0 / 2.2) * 255.0).round() as u8;
+            }
+            image.put_pixel(x as u32, y as u32, Rgb(px));
+        }
+    }
+    info!("Demosaiced {w}x{h} {bits} bit CFA image with pattern {pattern:?}");
+
+    Ok(DynamicImage::ImageRgb8(image))
+}
+
+/// A minimal TIFF reader, enough to walk the IFDs of RAW files.
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+struct Ifd {
+    entries: Vec<Entry>,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: usize,
+    value_offset: usize,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        // some vendors use their own magic number, e.g. "IIRO" for ORF
+        let little_endian = match data.get(0..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        Some(Self {
+            data,
+            little_endian,
+        })
+    }
+
+    fn read_u16(&self, b: &[u8]) -> u16 {
+        let b = [b[0], b[1]];
+        if self.little_endian {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        }
+    }
+
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        self.data.get(offset..offset + 2).map(|b| self.read_u16(b))
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let b: [u8; 4] = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+
+    /// All IFDs reachable from the header through next pointers, SubIFDs and
+    /// the Exif IFD.
+    fn ifds(&self) -> Vec<Ifd> {
+        let mut ifds = vec![];
+        let mut seen = HashSet::new();
+        let mut queue = vec![self.u32_at(4).unwrap_or(0) as usize];
+        while let Some(offset) = queue.pop() {
+            if offset == 0 || !seen.insert(offset) {
+                continue;
+            }
+            let Some((ifd, next)) = self.ifd_at(offset) else {
+                continue;
+            };
+            queue.push(next);
+            for t in [tag::SUB_IFDS, tag::EXIF_IFD] {
+                queue.extend(ifd.uints(self, t).into_iter().map(|o| o as usize));
+            }
+            ifds.push(ifd);
+        }
+        ifds
+    }
+
+    fn ifd_at(&self, offset: usize) -> Option<(Ifd, usize)> {
+        let n = self.u16_at(offset)? as usize;
+        let mut entries = Vec::with_capacity(n);
+        for i in 0..n {
+            let at = offset + 2 + i * 12;
+            let kind = self.u16_at(at + 2)?;
+            let count = self.u32_at(at + 4)? as usize;
+            let size = count.saturating_mul(type_size(kind));
+            let value_offset = if size <= 4 {
+                at + 8
+            } else {
+                self.u32_at(at + 8)? as usize
+            };
+            // the count comes from the file, only as many values as the data
+            // holds are ever read
+            let available = self.data.len().saturating_sub(value_offset);
+            let count = count.min(available / type_size(kind).max(1));
+            entries.push(Entry {
+                tag: self.u16_at(at)?,
+                kind,
+                count,
+                value_offset,
+            });
+        }
+        let next = self.u32_at(offset + 2 + n * 12).unwrap_or(0) as usize;
+        Some((Ifd { entries }, next))
+    }
+}
+
+fn type_size(kind: u16) -> usize {
+    match kind {
+        1 | 2 | 6 | 7 => 1,
+        3 | 8 => 2,
+        4 | 9 | 11 | 13 => 4,
+        5 | 10 | 12 => 8,
+        _ => 0,
+    }
+}
+
+impl Ifd {
+    fn get(&self, tag: u16) -> Option<Entry> {
+        self.entries.iter().find(|e| e.tag == tag).copied()
+    }
+
+    fn uints(&self, tiff: &Tiff, tag: u16) -> Vec<u32> {
+        let Some(e) = self.get(tag) else {
+            return vec![];
+        };
+        (0..e.count)
+            .filter_map(|i| match e.kind {
+                1 | 7 => tiff.data.get(e.value_offset + i).map(|b| *b as u32),
+                3 => tiff.u16_at(e.value_offset + i * 2).map(|v| v as u32),
+                4 | 13 => tiff.u32_at(e.value_offset + i * 4),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn uint(&self, tiff: &Tiff, tag: u16) -> Option<u32> {
+        self.uints(tiff, tag).first().copied()
+    }
+
+    fn rationals(&self, tiff: &Tiff, tag: u16) -> Option<Vec<f64>> {
+        let e = self.get(tag).filter(|e| e.kind == 5 || e.kind == 10)?;
+        (0..e.count)
+            .map(|i| {
+                let at = e.value_offset + i * 8;
+                let (num, den) = (tiff.u32_at(at)?, tiff.u32_at(at + 4)?);
+                let value = if e.kind == 10 {
+                    num as i32 as f64 / den as i32 as f64
+                } else {
+                    num as f64 / den as f64
+                };
+                (den != 0).then_some(value)
+            })
+            .collect()
+    }
+
+    fn rational(&self, tiff: &Tiff, tag: u16) -> Option<f64> {
+        self.rationals(tiff, tag)?.first().copied()
+    }
+
+    fn bytes<'a>(&self, tiff: &Tiff<'a>, tag: u16) -> Option<&'a [u8]> {
+        let e = self.get(tag)?;
+        tiff.data.get(e.value_offset..e.value_offset + e.count)
+    }
+
+    fn ascii(&self, tiff: &Tiff, tag: u16) -> Option<String> {
+        let bytes = self.bytes(tiff, tag)?;
+        let s = String::from_utf8_lossy(bytes);
+        let s = s.trim_end_matches('\0').trim();
+        (!s.is_empty()).then(|| s.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A little endian TIFF with one IFD holding `entries` of (tag, kind,
+    /// count, value), followed by `extra` data starting at offset 100.
+    fn tiff(entries: &[(u16, u16, u32, u32)], extra: &[u8]) -> Vec<u8> {
+        let mut data = b"II*\0".to_vec();
+        data.extend(8u32.to_le_bytes());
+        data.extend((entries.len() as u16).to_le_bytes());
+        for (tag, kind, count, value) in entries {
+            data.extend(tag.to_le_bytes());
+            data.extend(kind.to_le_bytes());
+            data.extend(count.to_le_bytes());
+            data.extend(value.to_le_bytes());
+        }
+        data.extend(0u32.to_le_bytes());
+        data.resize(100, 0);
+        data.extend(extra);
+        data
+    }
+
+    #[test]
+    fn reads_inline_and_offset_values() {
+        let mut extra = vec![];
+        for v in [10u32, 20, 30] {
+            extra.extend(v.to_le_bytes());
+        }
+        extra.extend([1, 0, 0, 0, 4, 0, 0, 0]);
+        let data = tiff(
+            &[
+                (tag::IMAGE_WIDTH, 3, 1, 640),
+                (tag::STRIP_OFFSETS, 4, 3, 100),
+                (tag::EXPOSURE_TIME, 5, 1, 112),
+            ],
+            &extra,
+        );
+        let tiff = Tiff::new(&data).unwrap();
+        let ifds = tiff.ifds();
+        assert_eq!(ifds.len(), 1);
+        assert_eq!(ifds[0].uint(&tiff, tag::IMAGE_WIDTH), Some(640));
+        assert_eq!(ifds[0].uints(&tiff, tag::STRIP_OFFSETS), vec![10, 20, 30]);
+        assert_eq!(ifds[0].rational(&tiff, tag::EXPOSURE_TIME), Some(0.25));
+        assert_eq!(ifds[0].uint(&tiff, tag::IMAGE_LENGTH), None);
+    }
+
+    #[test]
+    fn limits_counts_to_the_data() {
+        let data = tiff(
+            &[
+                (tag::STRIP_OFFSETS, 4, u32::MAX, 100),
+                (tag::F_NUMBER, 5, u32::MAX, 100),
+                (tag::MAKE, 2, u32::MAX, 100),
+            ],
+            &[7, 0, 0, 0, 1, 0, 0, 0],
+        );
+        let tiff = Tiff::new(&data).unwrap();
+        let ifd = &tiff.ifds()[0];
+        assert_eq!(ifd.uints(&tiff, tag::STRIP_OFFSETS), vec![7, 1]);
+        assert_eq!(ifd.rationals(&tiff, tag::F_NUMBER), Some(vec![7.0]));
+        assert_eq!(ifd.bytes(&tiff, tag::MAKE).map(<[u8]>::len), Some(8));
+    }
+
+    #[test]
+    fn survives_ifd_loops() {
+        let mut data = tiff(&[(tag::IMAGE_WIDTH, 3, 1, 1)], &[]);
+        // the next IFD pointer leads back to the first one
+        data[22..26].copy_from_slice(&8u32.to_le_bytes());
+        let tiff = Tiff::new(&data).unwrap();
+        assert_eq!(tiff.ifds().len(), 1);
+        assert!(Tiff::new(b"GIF89a").is_none());
+    }
+
+    #[test]
+    fn rejects_sizes_the_strips_dont_hold() {
+        let cfa = |w: u32, h: u32, len: u32| {
+            tiff(
+                &[
+                    (tag::IMAGE_WIDTH, 4, 1, w),
+                    (tag::IMAGE_LENGTH, 4, 1, h),
+                    (tag::BITS_PER_SAMPLE, 3, 1, 8),
+                    (tag::PHOTOMETRIC, 3, 1, PHOTOMETRIC_CFA),
+                    (tag::STRIP_OFFSETS, 4, 1, 100),
+                    (tag::STRIP_BYTE_COUNTS, 4, 1, len),
+                ],
+                &[0; 16],
+            )
+        };
+        for (w, h, len, reason) in [
+            (u32::MAX, u32::MAX, 16, "truncated strips"),
+            (0, 4, 16, "invalid image size"),
+            (4, 4, 8, "truncated strips"),
+            (4, 4, 64, "truncated strips"),
+        ] {
+            let data = cfa(w, h, len);
+            let tiff = Tiff::new(&data).unwrap();
+            match demosaic(&tiff, &tiff.ifds()) {
+                Err(RawError::Unsupported(r)) => assert_eq!(r, reason),
+                res => panic!("{w}x{h} from {len} bytes: {res:?}"),
+            }
+        }
+        let data = cfa(4, 4, 16);
+        let tiff = Tiff::new(&data).unwrap();
+        let image = demosaic(&tiff, &tiff.ifds()).unwrap();
+        assert_eq!((image.width(), image.height()), (4, 4));
+    }
+}
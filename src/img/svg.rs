@@ -0,0 +1,73 @@
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use image::{Rgb, RgbImage};
+use resvg::{
+    tiny_skia::{Pixmap, Transform},
+    usvg::{Options, Tree},
+};
+
+/// Whether `path` looks like an SVG document, judging by its extension or,
+/// failing that, its first bytes.
+pub fn is_svg(path: &Path) -> bool {
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        if matches!(ext.to_ascii_lowercase().as_str(), "svg" | "svgz") {
+            return true;
+        }
+    }
+
+    let mut head = [0; 256];
+    let Ok(n) = File::open(path).and_then(|mut f| f.read(&mut head)) else {
+        return false;
+    };
+    let head = String::from_utf8_lossy(&head[..n]);
+    let head = head.trim_start();
+    head.starts_with("<svg") || (head.starts_with("<?xml") && head.contains("<svg"))
+}
+
+pub fn load(path: &Path) -> Result<Tree> {
+    let data = fs::read(path)?;
+    let mut options = Options {
+        resources_dir: path.parent().map(Path::to_path_buf),
+        ..Default::default()
+    };
+    options.fontdb_mut().load_system_fonts();
+    let tree = Tree::from_data(&data, &options)?;
+    info!("Parsed SVG document {path:?}");
+    Ok(tree)
+}
+
+pub fn dimensions(tree: &Tree) -> (u32, u32) {
+    let size = tree.size().to_int_size();
+    (size.width(), size.height())
+}
+
+/// Rasterises `tree` at `scale`, composited onto a white background since
+/// most SVGs rely on transparency.
+pub fn rasterize(tree: &Tree, scale: f32) -> Result<RgbImage> {
+    let (w, h) = dimensions(tree);
+    let (w, h) = (
+        ((w as f32 * scale).round() as u32).max(1),
+        ((h as f32 * scale).round() as u32).max(1),
+    );
+    let mut pixmap = Pixmap::new(w, h).ok_or_else(|| anyhow!("Invalid SVG size {w}x{h}"))?;
+    resvg::render(
+        tree,
+        Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    let mut buffer = RgbImage::new(w, h);
+    for (px, out) in pixmap.pixels().iter().zip(buffer.pixels_mut()) {
+        // pixels are premultiplied, so compositing over white is just adding
+        // the uncovered share of white
+        let bg = 255 - px.alpha();
+        *out = Rgb([px.red() + bg, px.green() + bg, px.blue() + bg]);
+    }
+    info!("Rasterized SVG at {w}x{h} (scale {scale})");
+    Ok(buffer)
+}
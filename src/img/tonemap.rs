@@ -0,0 +1,174 @@
+//! Converts decoded images of any bit depth to 8 or 10 bit sRGB for display.
+//! Samples are linearised, scaled by the exposure, compressed with the tone
+//! mapping operator and dithered down to the output depth.
+
+use clap::ValueEnum;
+use image::{DynamicImage, ImageBuffer, Rgb, RgbImage};
+
+/// 4x4 Bayer matrix, normalised to offsets in (-0.5, 0.5) of one output step.
+static BAYER: [[f32; 4]; 4] = [
+    [0.0, 8.0, 2.0, 10.0],
+    [12.0, 4.0, 14.0, 6.0],
+    [3.0, 11.0, 1.0, 9.0],
+    [15.0, 7.0, 13.0, 5.0],
+];
+
+pub static EXPOSURE_STEP: f32 = 0.5;
+pub static MAX_EXPOSURE: f32 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ToneMap {
+    /// Clip everything above white
+    Clip,
+    /// x / (1 + x)
+    Reinhard,
+    /// Filmic curve approximating the ACES reference transform
+    Aces,
+}
+
+impl ToneMap {
+    pub fn next(self) -> Self {
+        match self {
+            ToneMap::Clip => ToneMap::Reinhard,
+            ToneMap::Reinhard => ToneMap::Aces,
+            ToneMap::Aces => ToneMap::Clip,
+        }
+    }
+
+    fn apply(self, v: f32) -> f32 {
+        match self {
+            ToneMap::Clip => v,
+            ToneMap::Reinhard => v / (1.0 + v),
+            ToneMap::Aces => (v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14),
+        }
+    }
+}
+
+impl std::fmt::Display for ToneMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ToneMap::Clip => "clip",
+            ToneMap::Reinhard => "Reinhard",
+            ToneMap::Aces => "ACES",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// How an image is mapped to the display.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub op: ToneMap,
+    /// Exposure adjustment in stops.
+    pub exposure: f32,
+}
+
+impl Tone {
+    /// The default for `image`: floating point images hold linear scene
+    /// values that need compressing, everything else is already display
+    /// referred.
+    pub fn for_image(image: &DynamicImage, op: Option<ToneMap>, exposure: f32) -> Self {
+        let op = op.unwrap_or(if is_float(image) {
+            ToneMap::Reinhard
+        } else {
+            ToneMap::Clip
+        });
+        Self { op, exposure }
+    }
+
+    pub fn adjust_exposure(&mut self, delta: f32) {
+        self.exposure = (self.exposure + delta).clamp(-MAX_EXPOSURE, MAX_EXPOSURE);
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.op == ToneMap::Clip && self.exposure == 0.0
+    }
+}
+
+pub fn is_float(image: &DynamicImage) -> bool {
+    matches!(
+        image,
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+    )
+}
+
+/// Bits per channel of the decoded image.
+pub fn bit_depth(image: &DynamicImage) -> u16 {
+    let color = image.color();
+    color.bits_per_pixel() / color.channel_count() as u16
+}
+
+/// Describes the bit depth of `image` for the file info.
+pub fn describe_depth(image: &DynamicImage) -> String {
+    let bits = bit_depth(image);
+    if is_float(image) {
+        format!("{bits}-bit float")
+    } else {
+        format!("{bits}-bit")
+    }
+}
+
+/// 10 bit samples, stored in the low bits of each `u16`.
+pub type Rgb10Image = ImageBuffer<Rgb<u16>, Vec<u16>>;
+
+/// Maps `image` to 8 bit sRGB with `tone`.
+pub fn to_rgb8(image: &DynamicImage, tone: Tone) -> RgbImage {
+    if bit_depth(image) == 8 && tone.is_identity() {
+        return image.to_rgb8();
+    }
+
+    let mut buffer = RgbImage::new(image.width(), image.height());
+    map_pixels(image, tone, u8::MAX as f32, |x, y, px| {
+        buffer.put_pixel(x, y, Rgb(px.map(|v| v as u8)))
+    });
+    buffer
+}
+
+/// Maps `image` to 10 bit sRGB with `tone`, for deep colour visuals.
+pub fn to_rgb10(image: &DynamicImage, tone: Tone) -> Rgb10Image {
+    let mut buffer = Rgb10Image::new(image.width(), image.height());
+    map_pixels(image, tone, 1023.0, |x, y, px| {
+        buffer.put_pixel(x, y, Rgb(px.map(|v| v as u16)))
+    });
+    buffer
+}
+
+/// Tone maps every pixel of `image` and hands it to `put`, dithered and
+/// quantised to integers in `0..=max`.
+fn map_pixels(image: &DynamicImage, tone: Tone, max: f32, mut put: impl FnMut(u32, u32, [f32; 3])) {
+    let gain = 2f32.powf(tone.exposure);
+    let linear = if is_float(image) {
+        image.to_rgb32f()
+    } else {
+        let mut linear = image.to_rgb32f();
+        linear
+            .pixels_mut()
+            .for_each(|px| px.0.iter_mut().for_each(|v| *v = srgb_to_linear(*v)));
+        linear
+    };
+
+    for (x, y, px) in linear.enumerate_pixels() {
+        let dither = BAYER[y as usize % 4][x as usize % 4] / 16.0 - 0.5 + 1.0 / 32.0;
+        let out = px.0.map(|v| {
+            let v = linear_to_srgb(tone.op.apply(v.max(0.0) * gain).min(1.0));
+            (v * max + 0.5 + dither).clamp(0.0, max).floor()
+        });
+        put(x, y, out);
+    }
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
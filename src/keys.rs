@@ -1,11 +1,66 @@
+use std::fmt::Display;
+
+use anyhow::Result;
+use x11rb::{
+    connection::Connection,
+    protocol::xproto::{ConnectionExt, KeyButMask, Keycode, Keysym},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Key {
+    A,
+    B,
+    C,
+    D,
+    E,
     F,
+    G,
+    H,
     I,
+    J,
+    K,
+    L,
     M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    Num0,
+    Num1,
+    Num2,
+    Num3,
+    Num4,
+    Num5,
+    Num6,
+    Num7,
+    Num8,
+    Num9,
     Up,
     Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
     Esc,
     Enter,
+    Space,
+    Backspace,
+    Delete,
+    Tab,
+    Minus,
+    Equal,
+    F2,
+    F5,
+    F6,
     Unknown,
 }
 
@@ -13,13 +68,171 @@ impl From<u8> for Key {
     fn from(value: u8) -> Self {
         match value {
             9 => Key::Esc,
+            10 => Key::Num1,
+            11 => Key::Num2,
+            12 => Key::Num3,
+            13 => Key::Num4,
+            14 => Key::Num5,
+            15 => Key::Num6,
+            16 => Key::Num7,
+            17 => Key::Num8,
+            18 => Key::Num9,
+            19 => Key::Num0,
+            20 => Key::Minus,
+            21 => Key::Equal,
+            22 => Key::Backspace,
+            23 => Key::Tab,
+            24 => Key::Q,
+            25 => Key::W,
+            26 => Key::E,
+            27 => Key::R,
+            28 => Key::T,
+            29 => Key::Y,
+            30 => Key::U,
             31 => Key::I,
+            32 => Key::O,
+            33 => Key::P,
             36 => Key::Enter,
+            38 => Key::A,
+            39 => Key::S,
+            40 => Key::D,
             41 => Key::F,
+            42 => Key::G,
+            43 => Key::H,
+            44 => Key::J,
+            45 => Key::K,
+            46 => Key::L,
+            52 => Key::Z,
+            53 => Key::X,
+            54 => Key::C,
+            55 => Key::V,
+            56 => Key::B,
+            57 => Key::N,
             58 => Key::M,
+            65 => Key::Space,
+            68 => Key::F2,
+            71 => Key::F5,
+            72 => Key::F6,
             111 => Key::Up,
+            112 => Key::PageUp,
+            113 => Key::Left,
+            114 => Key::Right,
             116 => Key::Down,
+            117 => Key::PageDown,
+            119 => Key::Delete,
             _ => Key::Unknown,
         }
     }
 }
+
+impl Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Key::A => "a",
+            Key::B => "b",
+            Key::C => "c",
+            Key::D => "d",
+            Key::E => "e",
+            Key::F => "f",
+            Key::G => "g",
+            Key::H => "h",
+            Key::I => "i",
+            Key::J => "j",
+            Key::K => "k",
+            Key::L => "l",
+            Key::M => "m",
+            Key::N => "n",
+            Key::O => "o",
+            Key::P => "p",
+            Key::Q => "q",
+            Key::R => "r",
+            Key::S => "s",
+            Key::T => "t",
+            Key::U => "u",
+            Key::V => "v",
+            Key::W => "w",
+            Key::X => "x",
+            Key::Y => "y",
+            Key::Z => "z",
+            Key::Num0 => "0",
+            Key::Num1 => "1",
+            Key::Num2 => "2",
+            Key::Num3 => "3",
+            Key::Num4 => "4",
+            Key::Num5 => "5",
+            Key::Num6 => "6",
+            Key::Num7 => "7",
+            Key::Num8 => "8",
+            Key::Num9 => "9",
+            Key::Up => "Up",
+            Key::Down => "Down",
+            Key::Left => "Left",
+            Key::Right => "Right",
+            Key::PageUp => "Prior",
+            Key::PageDown => "Next",
+            Key::Esc => "Escape",
+            Key::Enter => "Return",
+            Key::Space => "space",
+            Key::Backspace => "BackSpace",
+            Key::Delete => "Delete",
+            Key::Tab => "Tab",
+            Key::Minus => "minus",
+            Key::Equal => "equal",
+            Key::F2 => "F2",
+            Key::F5 => "F5",
+            Key::F6 => "F6",
+            Key::Unknown => "Unknown",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The server's keycode to keysym mapping, used to turn key events into
+/// typed characters for text input.
+pub struct KeyMap {
+    min_keycode: Keycode,
+    keysyms_per_keycode: usize,
+    keysyms: Vec<Keysym>,
+}
+
+impl KeyMap {
+    pub fn new<C: Connection>(conn: &C) -> Result<Self> {
+        let setup = conn.setup();
+        let (min, max) = (setup.min_keycode, setup.max_keycode);
+        let reply = conn.get_keyboard_mapping(min, max - min + 1)?.reply()?;
+        info!("Loaded keyboard mapping for keycodes {min}-{max}");
+        Ok(Self {
+            min_keycode: min,
+            keysyms_per_keycode: reply.keysyms_per_keycode as usize,
+            keysyms: reply.keysyms,
+        })
+    }
+
+    /// The printable Latin-1 character typed by `keycode` with the modifiers
+    /// in `state`, if any.
+    pub fn char_for(&self, keycode: Keycode, state: KeyButMask) -> Option<char> {
+        let state = u16::from(state);
+        let shift = state & u16::from(KeyButMask::SHIFT) != 0;
+        let lock = state & u16::from(KeyButMask::LOCK) != 0;
+
+        let offset = keycode.checked_sub(self.min_keycode)? as usize * self.keysyms_per_keycode;
+        let syms = self
+            .keysyms
+            .get(offset..offset + self.keysyms_per_keycode.min(2))?;
+        let (sym, upper) = match syms {
+            [_, upper] if shift && *upper != 0 => (*upper, false),
+            [lower, ..] => (*lower, shift != lock),
+            [] => return None,
+        };
+
+        let c = match sym {
+            0x20..=0x7e | 0xa0..=0xff => char::from_u32(sym)?,
+            _ => return None,
+        };
+        if upper {
+            c.to_uppercase().next()
+        } else {
+            Some(c)
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use crate::CLI;
+
+/// How files are put on screen: one at a time, or two side by side like the
+/// pages of a book.
+#[derive(Debug, Clone, Copy)]
+pub struct Layout {
+    pub spread: bool,
+    /// Read right to left, which swaps the arrow keys and puts the second
+    /// page of a spread on the left.
+    pub rtl: bool,
+    /// Show the first file on its own, so spreads start on odd pages.
+    pub cover: bool,
+    /// Space between the pages of a spread, in pixels.
+    pub gap: u16,
+}
+
+impl Layout {
+    pub fn from_cli() -> Self {
+        Self {
+            // comparing takes over the second image
+            spread: CLI.spread && !CLI.compare,
+            rtl: CLI.rtl,
+            cover: CLI.cover,
+            gap: CLI.spread_gap,
+        }
+    }
+
+    /// The first file of the spread holding file `i`.
+    pub fn spread_start(&self, i: usize) -> usize {
+        match (self.spread, self.cover) {
+            (false, _) => i,
+            (true, true) if i == 0 => 0,
+            (true, true) => i - (i - 1) % 2,
+            (true, false) => i - i % 2,
+        }
+    }
+
+    /// The file shown next to `start`, if any.
+    pub fn partner(&self, start: usize, len: usize) -> Option<usize> {
+        if !self.spread || (self.cover && start == 0) {
+            return None;
+        }
+        (start + 1 < len).then_some(start + 1)
+    }
+
+    pub fn next_start(&self, start: usize, len: usize) -> usize {
+        let next = self.partner(start, len).unwrap_or(start) + 1;
+        if next >= len {
+            0
+        } else {
+            next
+        }
+    }
+
+    pub fn prev_start(&self, start: usize, len: usize) -> usize {
+        self.spread_start(start.checked_sub(1).unwrap_or(len - 1))
+    }
+
+    /// The area each page is fitted into on a screen of `(w, h)`.
+    pub fn page_area(&self, (w, h): (u32, u32)) -> (u32, u32) {
+        if self.spread {
+            (w.saturating_sub(self.gap as u32) / 2, h)
+        } else {
+            (w, h)
+        }
+    }
+
+    /// Offsets of the pages with the given sizes from the top left corner of
+    /// the spread, in order, along with the size of the whole spread. Pages
+    /// are centred vertically.
+    pub fn arrange(&self, sizes: &[(u16, u16)]) -> (Vec<(i32, i32)>, (i32, i32)) {
+        let gaps = sizes.len().saturating_sub(1) as i32 * self.gap as i32;
+        let w = sizes.iter().map(|(w, _)| *w as i32).sum::<i32>() + gaps;
+        let h = sizes.iter().map(|(_, h)| *h as i32).max().unwrap_or(0);
+
+        let mut x = 0;
+        let mut offsets = vec![];
+        for (pw, ph) in sizes {
+            offsets.push((x, (h - *ph as i32) / 2));
+            x += *pw as i32 + self.gap as i32;
+        }
+        (offsets, (w, h))
+    }
+}
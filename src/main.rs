@@ -3,26 +3,47 @@ mod log;
 #[macro_use]
 mod util;
 mod app;
+mod archive;
+mod capture;
 mod cli;
+mod compare;
+mod crop;
+mod diff;
 mod event;
+mod exec;
+mod export;
+mod fileops;
+mod files;
 mod font;
 mod img;
 mod keys;
+mod layout;
 mod menu;
+mod prompt;
+mod resize;
 mod screen;
 mod state;
+mod wallpaper;
 
 use anyhow::Result;
 use app::Mevi;
 use clap::Parser;
-use cli::Cli;
-use img::MeviImage;
+use cli::{Cli, Command, NOTHING_MARKED_EXIT_CODE};
+use files::FileList;
+use img::{Look, MeviImage};
+use layout::Layout;
 use lazy_static::lazy_static;
 use log::LogType;
+use std::sync::Arc;
 use x11rb::connection::Connection;
 
 lazy_static! {
-    static ref CLI: Cli = Cli::parse();
+    // the test harness' arguments aren't mevi's
+    static ref CLI: Cli = if cfg!(test) {
+        Cli::parse_from(["mevi", "test"])
+    } else {
+        Cli::parse()
+    };
 }
 
 x11rb::atom_manager! {
@@ -36,28 +57,77 @@ x11rb::atom_manager! {
         _NET_WM_NAME,
         _NET_WM_STATE,
         _NET_WM_STATE_FULLSCREEN,
+        _MEVI_HISTOGRAM_READY,
     }
 }
 
 fn main() -> Result<()> {
+    if let Some(Command::Diff(args)) = &CLI.command {
+        std::process::exit(diff::run(args));
+    }
+    if let Some(Command::Export(args)) = &CLI.command {
+        std::process::exit(export::run(args));
+    }
+
     let (conn, screen_num) = x11rb::connect(None)?;
     info!("Connected to the X server");
+    // shared with the threads that wake up the event loop
+    let shared = Arc::new(conn);
+    let conn = &*shared;
 
     let screen = &conn.setup().roots[screen_num];
     info!("Got screen handle");
 
-    let pixel_layout = screen::pixel_layout_from_visual(screen, screen.root_visual)?;
+    let pixel_format = screen::pixel_format_from_visual(conn, screen, screen.root_visual)?;
 
-    let image = MeviImage::new(&conn, screen, &CLI.path, pixel_layout)?;
+    img::icc::init_monitor_profile(conn, screen)?;
 
-    let bg_img = img::get_bg_image(&conn, pixel_layout)?;
+    let mut files = if CLI.capture || CLI.region {
+        let image = capture::grab(conn, screen, &pixel_format, CLI.region)?;
+        FileList::captured(capture::file_name().into(), image)
+    } else {
+        FileList::new(&CLI.paths)
+    };
+    let page_area = Layout::from_cli().page_area((
+        screen.width_in_pixels as u32,
+        screen.height_in_pixels as u32,
+    ));
+    let image = loop {
+        match MeviImage::open(
+            conn,
+            files.current_entry(),
+            page_area,
+            pixel_format.clone(),
+            Look::default(),
+        ) {
+            Ok(image) => break image,
+            Err(e) if files.len() > 1 => {
+                err!("Failed to load {:?}: {e}", files.current());
+                files.remove(files.index());
+            }
+            Err(e) => return Err(e),
+        }
+    };
 
-    let atoms = Atoms::new(&conn)?.reply()?;
+    let bg_img = img::get_bg_image(conn, &pixel_format)?;
 
-    match Mevi::init(&conn, screen, atoms, image, bg_img) {
+    let atoms = Atoms::new(conn)?.reply()?;
+
+    match Mevi::init(&shared, screen, atoms, image, bg_img, pixel_format, files) {
         Ok(mut mevi) => {
             info!("Initialized Mevi!");
             mevi.run_event_loop()?;
+
+            if CLI.print_marked {
+                let marked = mevi.files.marked();
+                drop(mevi);
+                if marked.is_empty() {
+                    std::process::exit(NOTHING_MARKED_EXIT_CODE);
+                }
+                for path in marked {
+                    println!("{}", path.display());
+                }
+            }
         }
         Err(e) => {
             err!("{e:?}");
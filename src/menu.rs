@@ -5,7 +5,6 @@ use crate::{
     font::{FontDrawer, RenderLine, RenderString},
     screen::RenderVisualInfo,
     util::{Rect, StatefulRenderPicture, GRAY_RENDER_COLOR, LIGHT_GRAY_RENDER_COLOR},
-    xy_in_rect,
 };
 use anyhow::Result;
 use x11rb::{
@@ -22,11 +21,25 @@ use x11rb::{
 #[derive(Debug, Clone, Copy)]
 pub enum MenuAction {
     ToggleFileInfo,
+    ToggleHistogram,
     Fullscreen,
+    Compare,
+    Crop,
+    SaveAs,
+    Resize,
+    SetWallpaper,
+    ToggleMark,
+    Trash,
+    Rename,
+    CopyToDestination,
+    MoveToDestination,
+    Undo,
+    ResetAdjustments,
     Exit,
     None,
 }
 
+#[allow(clippy::redundant_allocation)]
 pub struct Menu<'m, C: Connection> {
     id: u32,
     conn: Rc<&'m C>,
@@ -76,6 +89,7 @@ impl MenuItem {
 }
 
 impl<'m, C: Connection> Menu<'m, C> {
+    #[allow(clippy::redundant_allocation)]
     pub fn create(
         conn: Rc<&'m C>,
         screen: &Screen,
@@ -89,10 +103,68 @@ impl<'m, C: Connection> Menu<'m, C> {
                 MenuAction::ToggleFileInfo,
                 RenderString::new(vec![RenderLine::new(&font_drawer, "Show file info")]).pad(5),
             ),
+            (
+                MenuAction::ToggleHistogram,
+                RenderString::new(vec![RenderLine::new(&font_drawer, "Show histogram")]).pad(5),
+            ),
             (
                 MenuAction::Fullscreen,
                 RenderString::new(vec![RenderLine::new(&font_drawer, "Fullscreen")]).pad(5),
             ),
+            (
+                MenuAction::Compare,
+                RenderString::new(vec![RenderLine::new(
+                    &font_drawer,
+                    "Compare with next file",
+                )])
+                .pad(5),
+            ),
+            (
+                MenuAction::Crop,
+                RenderString::new(vec![RenderLine::new(&font_drawer, "Crop")]).pad(5),
+            ),
+            (
+                MenuAction::SaveAs,
+                RenderString::new(vec![RenderLine::new(&font_drawer, "Save as...")]).pad(5),
+            ),
+            (
+                MenuAction::Resize,
+                RenderString::new(vec![RenderLine::new(&font_drawer, "Resize and save...")]).pad(5),
+            ),
+            (
+                MenuAction::SetWallpaper,
+                RenderString::new(vec![RenderLine::new(&font_drawer, "Set as wallpaper")]).pad(5),
+            ),
+            (
+                MenuAction::ToggleMark,
+                RenderString::new(vec![RenderLine::new(&font_drawer, "Toggle mark")]).pad(5),
+            ),
+            (
+                MenuAction::Trash,
+                RenderString::new(vec![RenderLine::new(&font_drawer, "Move to trash")]).pad(5),
+            ),
+            (
+                MenuAction::Rename,
+                RenderString::new(vec![RenderLine::new(&font_drawer, "Rename")]).pad(5),
+            ),
+            (
+                MenuAction::CopyToDestination,
+                RenderString::new(vec![RenderLine::new(&font_drawer, "Copy to destination")])
+                    .pad(5),
+            ),
+            (
+                MenuAction::MoveToDestination,
+                RenderString::new(vec![RenderLine::new(&font_drawer, "Move to destination")])
+                    .pad(5),
+            ),
+            (
+                MenuAction::Undo,
+                RenderString::new(vec![RenderLine::new(&font_drawer, "Undo")]).pad(5),
+            ),
+            (
+                MenuAction::ResetAdjustments,
+                RenderString::new(vec![RenderLine::new(&font_drawer, "Reset adjustments")]).pad(5),
+            ),
             (
                 MenuAction::Exit,
                 RenderString::new(vec![RenderLine::new(&font_drawer, "Exit")]).pad(5),
@@ -0,0 +1,52 @@
+use crate::{
+    font::{FontDrawer, RenderLine, RenderString},
+    resize::Resize,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptKind {
+    Rename,
+    SaveCrop,
+    SaveAs,
+    Resize,
+    /// Saving after the size was picked.
+    SaveResized(Resize),
+}
+
+pub enum PromptEvent {
+    Input(char),
+    Backspace,
+    Submit,
+    Cancel,
+}
+
+/// A single line text input drawn at the bottom of the window.
+#[derive(Debug)]
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub label: String,
+    pub text: String,
+}
+
+impl Prompt {
+    pub fn new(kind: PromptKind, label: impl ToString, text: impl ToString) -> Self {
+        Self {
+            kind,
+            label: label.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.text.push(c);
+    }
+
+    pub fn pop(&mut self) {
+        self.text.pop();
+    }
+
+    pub fn to_render_string(&self, font_drawer: &FontDrawer) -> RenderString {
+        let line = format!("{}: {}_", self.label, self.text);
+        RenderString::new(vec![RenderLine::new(font_drawer, line)]).pad(5)
+    }
+}
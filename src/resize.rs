@@ -0,0 +1,105 @@
+//! Sizes for the resize dialog, written like "50%", "800x600", "800x" or
+//! "x600", optionally followed by a filter name. Both dimensions given fit the
+//! image into that box unless a "!" unlocks the aspect ratio.
+
+use clap::ValueEnum;
+use image::imageops::FilterType;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ResizeError {
+    #[error("Invalid size {0:?}, expected e.g. 50%, 800x600, 800x600!, 800x or x600")]
+    InvalidSize(String),
+    #[error("Unknown filter {0:?}, expected nearest, triangle, catmull-rom, gaussian or lanczos3")]
+    UnknownFilter(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for FilterType {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+impl std::fmt::Display for ResizeFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ResizeFilter::Nearest => "nearest",
+            ResizeFilter::Triangle => "triangle",
+            ResizeFilter::CatmullRom => "catmull-rom",
+            ResizeFilter::Gaussian => "gaussian",
+            ResizeFilter::Lanczos3 => "lanczos3",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resize {
+    pub w: u32,
+    pub h: u32,
+    pub filter: ResizeFilter,
+}
+
+impl Resize {
+    /// Works out the size `spec` asks for from the image size `(ow, oh)`.
+    /// Lanczos3 is used unless another filter is named.
+    pub fn parse(spec: &str, (ow, oh): (u32, u32)) -> Result<Self, ResizeError> {
+        let mut words = spec.split_whitespace();
+        let size = words.next().unwrap_or_default();
+        let filter = match words.next() {
+            Some(name) => ResizeFilter::from_str(name, true)
+                .map_err(|_| ResizeError::UnknownFilter(name.into()))?,
+            None => ResizeFilter::Lanczos3,
+        };
+        let invalid = || ResizeError::InvalidSize(spec.trim().into());
+        if words.next().is_some() {
+            return Err(invalid());
+        }
+
+        let (fw, fh) = (ow as f32, oh as f32);
+        let scaled = |scale: f32| ((fw * scale).round() as u32, (fh * scale).round() as u32);
+        let (w, h) = if let Some(percent) = size.strip_suffix('%') {
+            let percent = percent.parse::<f32>().map_err(|_| invalid())?;
+            if !percent.is_finite() || percent <= 0.0 {
+                return Err(invalid());
+            }
+            scaled(percent / 100.0)
+        } else {
+            let (size, stretch) = match size.strip_suffix('!') {
+                Some(size) => (size, true),
+                None => (size, false),
+            };
+            let (w, h) = size.split_once('x').ok_or_else(invalid)?;
+            let dimension = |v: &str| match v {
+                "" => Ok(None),
+                v => v.parse::<u32>().map(Some).map_err(|_| invalid()),
+            };
+            match (dimension(w)?, dimension(h)?) {
+                (Some(w), Some(h)) if stretch => (w, h),
+                (Some(w), Some(h)) => scaled((w as f32 / fw).min(h as f32 / fh)),
+                (Some(w), None) => scaled(w as f32 / fw),
+                (None, Some(h)) => scaled(h as f32 / fh),
+                (None, None) => return Err(invalid()),
+            }
+        };
+        if w == 0 || h == 0 {
+            return Err(invalid());
+        }
+        Ok(Self { w, h, filter })
+    }
+}
@@ -1,3 +1,5 @@
+use std::rc::Rc;
+
 use anyhow::Result;
 use smallmap::Map;
 use thiserror::Error;
@@ -6,11 +8,13 @@ use x11rb::{
     image::PixelLayout,
     protocol::{
         render::{query_pict_formats, Directformat, PictType, Pictformat, Pictforminfo},
-        xproto::{Screen, VisualClass, Visualid, Visualtype},
+        xproto::{Screen, VisualClass, Visualid},
     },
     rust_connection::{ConnectionError, ParseError, ReplyError},
 };
 
+use crate::img::palette::Palette;
+
 #[derive(Error, Debug)]
 pub enum VisualError {
     #[error("Failed to query for pict formats: {0}")]
@@ -21,8 +25,18 @@ pub enum VisualError {
     NoAppropriateVisual,
     #[error("The server sent a malformed visual type: {0:?}")]
     Malformed(#[from] ParseError),
-    #[error("The root visual is not true or direct color, but {0:?}")]
-    NotTrueOrDirect(Visualtype),
+    #[error("Failed to allocate a palette: {0}")]
+    Palette(anyhow::Error),
+}
+
+/// How images are turned into pixel values for the root visual.
+#[derive(Debug, Clone)]
+pub enum PixelFormat {
+    /// TrueColor and DirectColor visuals, with the colour components packed
+    /// into each pixel.
+    Direct(PixelLayout),
+    /// Colormapped and grey visuals, with pixels indexing a palette.
+    Indexed(Rc<Palette>),
 }
 
 #[derive(Debug)]
@@ -31,6 +45,7 @@ pub struct RenderVisualInfo {
     pub render: VisualInfo,
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct VisualInfo {
     pub id: Visualid,
@@ -65,7 +80,10 @@ impl VisualInfo {
             .formats
             .into_iter()
             .filter_map(|pfi| {
-                (pfi.type_ == PictType::DIRECT && pfi.depth == depth).then_some((pfi.id, pfi))
+                // the root visual may be colormapped, which XRender handles
+                // through an indexed format
+                let usable = pfi.type_ == PictType::DIRECT || id.is_some();
+                (usable && pfi.depth == depth).then_some((pfi.id, pfi))
             })
             .collect::<Map<Pictformat, Pictforminfo>>();
         for screen in formats.screens {
@@ -98,7 +116,11 @@ impl VisualInfo {
     }
 }
 
-pub fn pixel_layout_from_visual(screen: &Screen, id: Visualid) -> Result<PixelLayout, VisualError> {
+pub fn pixel_format_from_visual<C: Connection>(
+    conn: &C,
+    screen: &Screen,
+    id: Visualid,
+) -> Result<PixelFormat, VisualError> {
     let visual_info = screen.allowed_depths.iter().find_map(|d| {
         let info = d.visuals.iter().find(|d| d.visual_id == id);
         info.map(|i| (d.depth, i))
@@ -108,12 +130,20 @@ pub fn pixel_layout_from_visual(screen: &Screen, id: Visualid) -> Result<PixelLa
         None => Err(VisualError::NoAppropriateVisual)?,
     };
 
-    match visual_type.class {
-        VisualClass::TRUE_COLOR | VisualClass::DIRECT_COLOR => {}
-        _ => Err(VisualError::NotTrueOrDirect(*visual_type))?,
-    };
+    if !matches!(
+        visual_type.class,
+        VisualClass::TRUE_COLOR | VisualClass::DIRECT_COLOR
+    ) {
+        let palette =
+            Palette::allocate(conn, screen, visual_type, depth).map_err(VisualError::Palette)?;
+        info!(
+            "Using a palette for {:?} visual {id}: {palette:?}",
+            visual_type.class
+        );
+        return Ok(PixelFormat::Indexed(Rc::new(palette)));
+    }
     let pixel_layout = PixelLayout::from_visual_type(*visual_type)?;
     assert_eq!(pixel_layout.depth(), depth);
     info!("Found pixel layout from visual {id}: {pixel_layout:?}");
-    Ok(pixel_layout)
+    Ok(PixelFormat::Direct(pixel_layout))
 }
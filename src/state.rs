@@ -9,10 +9,11 @@ use x11rb::{
     },
 };
 
-use crate::CLI;
+use crate::{compare::Compare, crop::Crop, layout::Layout, CLI};
 
 pub struct MeviState<'s, C: Connection> {
     pub window: WindowWrapper<'s, C>,
+    #[allow(dead_code)]
     pub menu: WindowWrapper<'s, C>,
     pub pms: Pms<'s, C>,
     pub gcs: Gcs<'s, C>,
@@ -20,7 +21,27 @@ pub struct MeviState<'s, C: Connection> {
     pub should_redraw: bool,
     pub should_exit: bool,
     pub draw_info: bool,
+    pub draw_histogram: bool,
     pub fullscreen: bool,
+    pub awaiting_handler_key: bool,
+    pub layout: Layout,
+    pub compare: Option<Compare>,
+    /// How far the images are moved from the centre of the window.
+    pub pan: (i32, i32),
+    pub drag: Option<Drag>,
+    /// The crop selection while cropping.
+    pub crop: Option<Crop>,
+}
+
+/// What a mouse drag is doing.
+#[derive(Debug, Clone, Copy)]
+pub enum Drag {
+    /// Panning the images, from the last pointer position.
+    Pan(i16, i16),
+    /// Moving the wipe line of the comparison.
+    Split,
+    /// Selecting the area to crop.
+    Crop,
 }
 
 pub struct Gcs<'s, C: Connection> {
@@ -31,8 +52,13 @@ pub struct Gcs<'s, C: Connection> {
 
 pub struct Pms<'s, C: Connection> {
     pub image: PixmapWrapper<'s, C>,
+    /// The second page of a spread.
+    pub spread_image: PixmapWrapper<'s, C>,
+    /// The differences between compared images.
+    pub diff_image: PixmapWrapper<'s, C>,
     pub buffer: PixmapWrapper<'s, C>,
     pub font_buffer: PixmapWrapper<'s, C>,
+    pub bar_font_buffer: PixmapWrapper<'s, C>,
     pub background: PixmapWrapper<'s, C>,
 }
 
@@ -40,6 +66,7 @@ pub struct Pics<'s, C: Connection> {
     pub window: PictureWrapper<'s, C>,
     pub buffer: PictureWrapper<'s, C>,
     pub font_buffer: PictureWrapper<'s, C>,
+    pub bar_font_buffer: PictureWrapper<'s, C>,
 }
 
 impl<C: Connection> Debug for Gcs<'_, C> {
@@ -58,10 +85,13 @@ impl<C: Connection> Debug for Pms<'_, C> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Pms {{ image: {}, buffer: {}, font_buffer: {}, background: {} }}",
+            "Pms {{ image: {}, spread_image: {}, diff_image: {}, buffer: {}, font_buffer: {}, bar_font_buffer: {}, background: {} }}",
             self.image.pixmap(),
+            self.spread_image.pixmap(),
+            self.diff_image.pixmap(),
             self.buffer.pixmap(),
             self.font_buffer.pixmap(),
+            self.bar_font_buffer.pixmap(),
             self.background.pixmap()
         )
     }
@@ -71,10 +101,11 @@ impl<C: Connection> Debug for Pics<'_, C> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Pics {{ window: {}, buffer: {}, font_buffer: {} }}",
+            "Pics {{ window: {}, buffer: {}, font_buffer: {}, bar_font_buffer: {} }}",
             self.window.picture(),
             self.buffer.picture(),
-            self.font_buffer.picture()
+            self.font_buffer.picture(),
+            self.bar_font_buffer.picture()
         )
     }
 }
@@ -85,8 +116,11 @@ impl<'s, C: Connection + Debug> MeviState<'s, C> {
         let menu = WindowWrapper::for_window(conn, conn.generate_id()?);
         let pms = Pms {
             image: PixmapWrapper::for_pixmap(conn, conn.generate_id()?),
+            spread_image: PixmapWrapper::for_pixmap(conn, conn.generate_id()?),
+            diff_image: PixmapWrapper::for_pixmap(conn, conn.generate_id()?),
             buffer: PixmapWrapper::for_pixmap(conn, conn.generate_id()?),
             font_buffer: PixmapWrapper::for_pixmap(conn, conn.generate_id()?),
+            bar_font_buffer: PixmapWrapper::for_pixmap(conn, conn.generate_id()?),
             background: PixmapWrapper::for_pixmap(conn, conn.generate_id()?),
         };
         let gcs = Gcs {
@@ -98,6 +132,7 @@ impl<'s, C: Connection + Debug> MeviState<'s, C> {
             window: PictureWrapper::for_picture(conn, conn.generate_id()?),
             buffer: PictureWrapper::for_picture(conn, conn.generate_id()?),
             font_buffer: PictureWrapper::for_picture(conn, conn.generate_id()?),
+            bar_font_buffer: PictureWrapper::for_picture(conn, conn.generate_id()?),
         };
 
         info!("Window: {}", window.window());
@@ -115,7 +150,14 @@ impl<'s, C: Connection + Debug> MeviState<'s, C> {
             should_redraw: false,
             should_exit: false,
             draw_info: CLI.info,
+            draw_histogram: CLI.histogram,
             fullscreen: false,
+            awaiting_handler_key: false,
+            layout: Layout::from_cli(),
+            compare: CLI.compare.then(|| Compare::new(CLI.compare_view)),
+            pan: (0, 0),
+            drag: None,
+            crop: None,
         };
         Ok(state)
     }
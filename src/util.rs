@@ -33,6 +33,61 @@ pub static WHITE_RENDER_COLOR: Color = Color {
     alpha: 0xffff,
 };
 
+pub static MARK_RENDER_COLOR: Color = Color {
+    red: 0xffff,
+    green: 0x8c8c,
+    blue: 0x0000,
+    alpha: 0xffff,
+};
+
+pub static MARK_INDICATOR_SIZE: u16 = 12;
+
+pub static CROP_SHADE_RENDER_COLOR: Color = Color {
+    red: 0x0000,
+    green: 0x0000,
+    blue: 0x0000,
+    alpha: 0x9000,
+};
+
+// histogram colours are premultiplied so the channels show through each other
+pub static HISTOGRAM_BG_RENDER_COLOR: Color = Color {
+    red: 0x0000,
+    green: 0x0000,
+    blue: 0x0000,
+    alpha: 0xb000,
+};
+
+pub static HISTOGRAM_LUMA_RENDER_COLOR: Color = Color {
+    red: 0x6000,
+    green: 0x6000,
+    blue: 0x6000,
+    alpha: 0x6000,
+};
+
+pub static HISTOGRAM_RED_RENDER_COLOR: Color = Color {
+    red: 0x9000,
+    green: 0x0000,
+    blue: 0x0000,
+    alpha: 0x9000,
+};
+
+pub static HISTOGRAM_GREEN_RENDER_COLOR: Color = Color {
+    red: 0x0000,
+    green: 0x9000,
+    blue: 0x0000,
+    alpha: 0x9000,
+};
+
+pub static HISTOGRAM_BLUE_RENDER_COLOR: Color = Color {
+    red: 0x0000,
+    green: 0x0000,
+    blue: 0x9000,
+    alpha: 0x9000,
+};
+
+pub static HISTOGRAM_HEIGHT: u16 = 100;
+pub static HISTOGRAM_PADDING: u16 = 8;
+
 #[derive(Clone, Copy, Debug)]
 pub struct Rect {
     pub x: i16,
@@ -45,6 +100,33 @@ impl Rect {
     pub fn new(x: i16, y: i16, w: u16, h: u16) -> Self {
         Self { x, y, w, h }
     }
+
+    /// Clips an image of `w` x `h` with its top left corner at `(x, y)` to
+    /// this rectangle. Returns where the visible part goes and which part of
+    /// the image it is, or `None` if nothing is visible.
+    pub fn clip(&self, (x, y): (i32, i32), (w, h): (u16, u16)) -> Option<(Rect, Rect)> {
+        let (left, top) = (self.x as i32, self.y as i32);
+        let (right, bottom) = (left + self.w as i32, top + self.h as i32);
+        let (x0, y0) = (x.max(left), y.max(top));
+        let x1 = (x + w as i32).min(right);
+        let y1 = (y + h as i32).min(bottom);
+        if x1 <= x0 || y1 <= y0 {
+            return None;
+        }
+
+        let (cw, ch) = ((x1 - x0) as u16, (y1 - y0) as u16);
+        let parent = Rect::new(x0 as i16, y0 as i16, cw, ch);
+        let child = Rect::new((x0 - x) as i16, (y0 - y) as i16, cw, ch);
+        Some((parent, child))
+    }
+
+    /// The top left corner that centres `(w, h)` in this rectangle.
+    pub fn centre(&self, (w, h): (u16, u16)) -> (i32, i32) {
+        (
+            self.x as i32 + (self.w as i32 - w as i32) / 2,
+            self.y as i32 + (self.h as i32 - h as i32) / 2,
+        )
+    }
 }
 
 impl From<Rect> for Rectangle {
@@ -64,6 +146,7 @@ pub struct StatefulRenderPicture {
     pub inactive: RenderPicture,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
 pub struct RenderPicture {
     pub picture: Picture,
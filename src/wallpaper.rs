@@ -0,0 +1,144 @@
+//! Setting an image as the desktop background. The pixmap is made on a
+//! connection of its own that is closed with `RetainPermanent`, so it stays
+//! after mevi exits, and is announced through `_XROOTPMAP_ID` and
+//! `ESETROOT_PMAP_ID` for compositors and pseudo-transparent terminals.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use image::{imageops, imageops::FilterType, DynamicImage, RgbImage};
+use x11rb::{
+    connection::Connection,
+    protocol::xproto::{
+        AtomEnum, ChangeWindowAttributesAux, CloseDown, ConnectionExt, CreateGCAux, PropMode,
+        Window,
+    },
+    wrapper::ConnectionExt as _,
+};
+
+use crate::{img, screen};
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        _XROOTPMAP_ID,
+        ESETROOT_PMAP_ID,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum WallpaperMode {
+    /// Scale to cover the screen, cutting off what doesn't fit
+    Fill,
+    /// Scale to fit the screen, with black bars
+    Fit,
+    /// Centre at the original size
+    Center,
+    /// Repeat at the original size from the top left corner
+    Tile,
+}
+
+impl std::fmt::Display for WallpaperMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            WallpaperMode::Fill => "fill",
+            WallpaperMode::Fit => "fit",
+            WallpaperMode::Center => "center",
+            WallpaperMode::Tile => "tile",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Draws `image` on the root window of the default screen in `mode`.
+pub fn set(image: &DynamicImage, mode: WallpaperMode) -> Result<()> {
+    let (conn, screen_num) = x11rb::connect(None)?;
+    let screen = &conn.setup().roots[screen_num];
+    let root = screen.root;
+    let (sw, sh) = (screen.width_in_pixels, screen.height_in_pixels);
+    let pixel_format = screen::pixel_format_from_visual(&conn, screen, screen.root_visual)?;
+
+    let canvas = compose(image, (sw as u32, sh as u32), mode);
+    let x11_image = img::to_x11_rgb8(&conn, canvas, &pixel_format)?;
+    let pixmap = conn.generate_id()?;
+    conn.create_pixmap(screen.root_depth, pixmap, root, sw, sh)?;
+    let gc = conn.generate_id()?;
+    conn.create_gc(gc, root, &CreateGCAux::default().graphics_exposures(0))?;
+    x11_image.put(&conn, pixmap, gc, 0, 0)?;
+    conn.free_gc(gc)?;
+
+    let atoms = Atoms::new(&conn)?.reply()?;
+    free_previous(&conn, root, &atoms)?;
+    for property in [atoms._XROOTPMAP_ID, atoms.ESETROOT_PMAP_ID] {
+        conn.change_property32(
+            PropMode::REPLACE,
+            root,
+            property,
+            AtomEnum::PIXMAP,
+            &[pixmap],
+        )?;
+    }
+    conn.change_window_attributes(
+        root,
+        &ChangeWindowAttributesAux::default().background_pixmap(pixmap),
+    )?;
+    conn.clear_area(false, root, 0, 0, 0, 0)?;
+    conn.set_close_down_mode(CloseDown::RETAIN_PERMANENT)?;
+    // make sure everything arrived before the connection goes away
+    conn.get_input_focus()?.reply()?;
+    info!("Set {sw}x{sh} wallpaper pixmap {pixmap} ({mode})");
+    Ok(())
+}
+
+/// Frees the pixmap of the wallpaper set before by any program following the
+/// Esetroot convention, which also kept its connection's resources around.
+fn free_previous(conn: &impl Connection, root: Window, atoms: &Atoms) -> Result<()> {
+    let pixmap_of = |property| -> Result<Option<u32>> {
+        let reply = conn
+            .get_property(false, root, property, AtomEnum::PIXMAP, 0, 1)?
+            .reply()?;
+        Ok(reply.value32().and_then(|mut v| v.next()))
+    };
+    let root_pmap = pixmap_of(atoms._XROOTPMAP_ID)?;
+    let esetroot_pmap = pixmap_of(atoms.ESETROOT_PMAP_ID)?;
+    if let (Some(old), Some(esetroot)) = (root_pmap, esetroot_pmap) {
+        if old == esetroot && old != 0 {
+            info!("Freeing previous wallpaper pixmap {old}");
+            conn.kill_client(old)?;
+        }
+    }
+    Ok(())
+}
+
+/// Lays `image` out on a black screen sized canvas.
+fn compose(image: &DynamicImage, (sw, sh): (u32, u32), mode: WallpaperMode) -> RgbImage {
+    let mut canvas = RgbImage::new(sw, sh);
+    let (iw, ih) = (image.width().max(1), image.height().max(1));
+    let (fx, fy) = (sw as f32 / iw as f32, sh as f32 / ih as f32);
+    let scale = match mode {
+        WallpaperMode::Fill => fx.max(fy),
+        WallpaperMode::Fit => fx.min(fy),
+        WallpaperMode::Center | WallpaperMode::Tile => 1.0,
+    };
+    let image = if scale == 1.0 {
+        image.to_rgb8()
+    } else {
+        let (w, h) = (
+            ((iw as f32 * scale).round() as u32).max(1),
+            ((ih as f32 * scale).round() as u32).max(1),
+        );
+        image.resize_exact(w, h, FilterType::Lanczos3).to_rgb8()
+    };
+
+    let (w, h) = image.dimensions();
+    if mode == WallpaperMode::Tile {
+        for y in (0..sh).step_by(h as usize) {
+            for x in (0..sw).step_by(w as usize) {
+                imageops::replace(&mut canvas, &image, x as i64, y as i64);
+            }
+        }
+    } else {
+        let x = (sw as i64 - w as i64) / 2;
+        let y = (sh as i64 - h as i64) / 2;
+        imageops::replace(&mut canvas, &image, x, y);
+    }
+    canvas
+}